@@ -1,7 +1,13 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?);
     tonic_build::configure()
         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
-        .compile(&["protos/grpc_service.proto"], &["protos"])
+        .message_attribute(".", "#[serde(default)]")
+        .file_descriptor_set_path(out_dir.join("triton_mock_descriptor.bin"))
+        .compile(
+            &["protos/grpc_service.proto", "protos/health.proto"],
+            &["protos"],
+        )
         .unwrap();
 
     Ok(())
@@ -0,0 +1,324 @@
+use crate::{
+    cassette::Cassettes,
+    http::error_response,
+    journal::{Journal, JournalFilter},
+    scenario::{ScenarioSpec, Scenarios},
+    stubs::{StubSpec, Stubs},
+    upstream::{Mode, Upstream},
+    RecordedStreams,
+};
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Json, Router,
+};
+
+use serde_json::json;
+
+use tokio::sync::Mutex;
+
+/// State behind the admin API: the cassettes, upstream connections, stubs,
+/// scenarios and request journal shared with every listener, and the file the
+/// default recording was last loaded from or saved to.
+#[derive(Debug)]
+pub struct Admin {
+    cassettes: Arc<Cassettes>,
+    upstream: Arc<Mutex<Upstream>>,
+    stubs: Arc<Mutex<Stubs>>,
+    scenarios: Arc<Mutex<Scenarios>>,
+    journal: Arc<Mutex<Journal>>,
+    recording: Mutex<String>,
+}
+
+impl Admin {
+    pub fn new_with(
+        cassettes: Arc<Cassettes>,
+        upstream: Arc<Mutex<Upstream>>,
+        stubs: Arc<Mutex<Stubs>>,
+        scenarios: Arc<Mutex<Scenarios>>,
+        journal: Arc<Mutex<Journal>>,
+        recording: String,
+    ) -> Self {
+        Admin {
+            cassettes,
+            upstream,
+            stubs,
+            scenarios,
+            journal,
+            recording: Mutex::new(recording),
+        }
+    }
+}
+
+type AdminState = Arc<Admin>;
+
+#[derive(serde::Deserialize)]
+struct RecordingJson {
+    path: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct ResetJson {
+    model: Option<String>,
+    session: Option<String>,
+    cassette: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct StateJson {
+    state: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ModeJson {
+    model: Option<String>,
+    mode: Mode,
+}
+
+async fn status(State(admin): State<AdminState>) -> Response {
+    // The recording path is locked first, as `load` and `save` do.
+    let recording = admin.recording.lock().await.clone();
+    let cassettes = admin.cassettes.loaded().await;
+    let recorded_streams = admin.cassettes.default_cassette();
+    let recorded_streams = recorded_streams.lock().await;
+    let models: Vec<&String> = recorded_streams.model_map.keys().collect();
+    let upstream = admin.upstream.lock().await;
+    Json(json!({
+        "recording": recording,
+        "models": models,
+        "cassettes": cassettes,
+        "sessions": recorded_streams.sessions(),
+        "default_mode": upstream.default_mode(),
+        "modes": upstream.modes(),
+    }))
+    .into_response()
+}
+
+/// Replaces the recording with the one in `path`, or the last loaded file.
+/// Replay starts from the beginning of the new recording.
+async fn load(State(admin): State<AdminState>, body: Option<Json<RecordingJson>>) -> Response {
+    let mut recording = admin.recording.lock().await;
+    let path = body
+        .and_then(|Json(body)| body.path)
+        .unwrap_or_else(|| recording.clone());
+    let loaded = RecordedStreams::load(&path).map_err(|e| e.to_string());
+    match loaded {
+        Ok(loaded) => {
+            log::info!("admin: loaded recording '{}'", path);
+            *admin.cassettes.default_cassette().lock().await = loaded;
+            *recording = path;
+            StatusCode::OK.into_response()
+        }
+        Err(e) => error_response(tonic::Status::invalid_argument(e)),
+    }
+}
+
+/// Empties the recording; replayed requests then miss until one is loaded.
+async fn unload(State(admin): State<AdminState>) -> Response {
+    log::info!("admin: unloaded recording");
+    *admin.cassettes.default_cassette().lock().await = RecordedStreams::default();
+    StatusCode::OK.into_response()
+}
+
+/// Writes the recording, including everything recorded so far, to `path` or
+/// the file it was loaded from, and every cassette recorded into to its file.
+async fn save(State(admin): State<AdminState>, body: Option<Json<RecordingJson>>) -> Response {
+    let mut recording = admin.recording.lock().await;
+    let path = body
+        .and_then(|Json(body)| body.path)
+        .unwrap_or_else(|| recording.clone());
+    let saved = {
+        let default_cassette = admin.cassettes.default_cassette();
+        let mut recorded_streams = default_cassette.lock().await;
+        let saved = recorded_streams.save(&path).map_err(|e| e.to_string());
+        recorded_streams.dirty &= saved.is_err();
+        saved
+    };
+    let saved = match saved {
+        Ok(()) => admin.cassettes.save_all().await.map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+    match saved {
+        Ok(()) => {
+            log::info!("admin: saved recording '{}'", path);
+            *recording = path;
+            StatusCode::OK.into_response()
+        }
+        Err(e) => error_response(tonic::Status::internal(e)),
+    }
+}
+
+/// Rewinds replay of one model, or of every model, to its first response, in
+/// one session or in all of them, of the default recording or `cassette`.
+async fn reset(State(admin): State<AdminState>, body: Option<Json<ResetJson>>) -> Response {
+    let (model, session, cassette) = body
+        .map(|Json(body)| (body.model, body.session, body.cassette))
+        .unwrap_or_default();
+    let recorded_streams = match &cassette {
+        Some(cassette) => match admin.cassettes.get(cassette).await {
+            Ok(recorded_streams) => recorded_streams,
+            Err(status) => return error_response(status),
+        },
+        None => admin.cassettes.default_cassette(),
+    };
+    log::info!(
+        "admin: reset replay of {} in {}",
+        model.as_deref().unwrap_or("all models"),
+        session
+            .as_deref()
+            .map_or("all sessions".to_string(), |s| format!("session '{}'", s))
+    );
+    recorded_streams
+        .lock()
+        .await
+        .reset_cursors(model.as_deref(), session.as_deref());
+    StatusCode::OK.into_response()
+}
+
+async fn mode(State(admin): State<AdminState>, Json(body): Json<ModeJson>) -> Response {
+    if let Some(model) = &body.model {
+        if !admin.cassettes.knows(model).await {
+            return error_response(tonic::Status::not_found(format!(
+                "unknown model '{}'",
+                model
+            )));
+        }
+    }
+    match Upstream::set_mode(&admin.upstream, body.model.as_deref(), body.mode).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(status) => error_response(status),
+    }
+}
+
+async fn add_stub(State(admin): State<AdminState>, Json(spec): Json<StubSpec>) -> Response {
+    if !admin.cassettes.knows(&spec.model).await {
+        return error_response(tonic::Status::invalid_argument(format!(
+            "unknown model '{}'",
+            spec.model
+        )));
+    }
+    match admin.stubs.lock().await.add(spec) {
+        Ok(id) => Json(json!({ "id": id })).into_response(),
+        Err(e) => error_response(tonic::Status::invalid_argument(e)),
+    }
+}
+
+async fn list_stubs(State(admin): State<AdminState>) -> Response {
+    Json(admin.stubs.lock().await.list()).into_response()
+}
+
+async fn clear_stubs(State(admin): State<AdminState>) -> Response {
+    admin.stubs.lock().await.clear();
+    StatusCode::OK.into_response()
+}
+
+async fn remove_stub(State(admin): State<AdminState>, Path(id): Path<u64>) -> Response {
+    if admin.stubs.lock().await.remove(id) {
+        StatusCode::OK.into_response()
+    } else {
+        error_response(tonic::Status::not_found(format!("unknown stub {}", id)))
+    }
+}
+
+async fn add_scenario(State(admin): State<AdminState>, Json(spec): Json<ScenarioSpec>) -> Response {
+    for step in &spec.steps {
+        if !admin.cassettes.knows(&step.model).await {
+            return error_response(tonic::Status::invalid_argument(format!(
+                "unknown model '{}'",
+                step.model
+            )));
+        }
+    }
+    match admin.scenarios.lock().await.add(spec) {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => error_response(tonic::Status::invalid_argument(e)),
+    }
+}
+
+async fn list_scenarios(State(admin): State<AdminState>) -> Response {
+    Json(admin.scenarios.lock().await.list()).into_response()
+}
+
+async fn clear_scenarios(State(admin): State<AdminState>) -> Response {
+    admin.scenarios.lock().await.clear();
+    StatusCode::OK.into_response()
+}
+
+/// Moves every scenario back to its initial state.
+async fn reset_scenarios(State(admin): State<AdminState>) -> Response {
+    admin.scenarios.lock().await.reset();
+    StatusCode::OK.into_response()
+}
+
+async fn remove_scenario(State(admin): State<AdminState>, Path(name): Path<String>) -> Response {
+    if admin.scenarios.lock().await.remove(&name) {
+        StatusCode::OK.into_response()
+    } else {
+        error_response(tonic::Status::not_found(format!(
+            "unknown scenario '{}'",
+            name
+        )))
+    }
+}
+
+async fn set_scenario_state(
+    State(admin): State<AdminState>,
+    Path(name): Path<String>,
+    Json(body): Json<StateJson>,
+) -> Response {
+    if admin.scenarios.lock().await.set_state(&name, body.state) {
+        StatusCode::OK.into_response()
+    } else {
+        error_response(tonic::Status::not_found(format!(
+            "unknown scenario '{}'",
+            name
+        )))
+    }
+}
+
+/// The journaled requests matching every filter in the query string, oldest
+/// first.
+async fn requests(
+    State(admin): State<AdminState>,
+    Query(filter): Query<JournalFilter>,
+) -> Response {
+    let journal = admin.journal.lock().await;
+    let requests = journal.find(&filter);
+    Json(json!({ "count": requests.len(), "requests": requests })).into_response()
+}
+
+async fn clear_requests(State(admin): State<AdminState>) -> Response {
+    admin.journal.lock().await.clear();
+    StatusCode::OK.into_response()
+}
+
+pub fn router(admin: AdminState) -> Router {
+    Router::new()
+        .route("/admin/status", get(status))
+        .route("/admin/recording/load", post(load))
+        .route("/admin/recording/unload", post(unload))
+        .route("/admin/recording/save", post(save))
+        .route("/admin/replay/reset", post(reset))
+        .route("/admin/mode", put(mode))
+        .route(
+            "/admin/stubs",
+            get(list_stubs).post(add_stub).delete(clear_stubs),
+        )
+        .route("/admin/stubs/:id", delete(remove_stub))
+        .route(
+            "/admin/scenarios",
+            get(list_scenarios)
+                .post(add_scenario)
+                .delete(clear_scenarios),
+        )
+        .route("/admin/scenarios/reset", post(reset_scenarios))
+        .route("/admin/scenarios/:name", delete(remove_scenario))
+        .route("/admin/scenarios/:name/state", put(set_scenario_state))
+        .route("/admin/requests", get(requests).delete(clear_requests))
+        .with_state(admin)
+}
@@ -0,0 +1,431 @@
+use crate::{journal::Caller, server, RecordedStreams};
+
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use tokio::sync::Mutex;
+
+use tonic::Status;
+
+/// The recordings the mock replays from and records into: the one given on
+/// the command line, and named cassettes from `--cassette-dir`, each stored as
+/// `<dir>/<name>.json.gz`, that requests choose with the cassette header.
+/// Named cassettes are loaded the first time a request asks for them.
+#[derive(Debug)]
+pub struct Cassettes {
+    default: Arc<Mutex<RecordedStreams>>,
+    dir: Option<PathBuf>,
+    header: String,
+    named: Mutex<BTreeMap<String, Arc<Mutex<RecordedStreams>>>>,
+    /// Models served besides the built-in ones and those recorded.
+    models: Vec<String>,
+    closed: AtomicBool,
+}
+
+impl Default for Cassettes {
+    fn default() -> Self {
+        Cassettes::new(Arc::default(), None, String::new(), Vec::new())
+    }
+}
+
+/// Whether `name` can be used as a file name in the cassette directory.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+impl Cassettes {
+    pub fn new(
+        default: Arc<Mutex<RecordedStreams>>,
+        dir: Option<PathBuf>,
+        header: String,
+        models: Vec<String>,
+    ) -> Self {
+        Cassettes {
+            default,
+            dir,
+            header,
+            named: Mutex::new(BTreeMap::new()),
+            models,
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Stops the recordings from counting as loaded once shutdown begins.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// The recording used by requests without the cassette header.
+    pub fn default_cassette(&self) -> Arc<Mutex<RecordedStreams>> {
+        self.default.clone()
+    }
+
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        Some(self.dir.as_ref()?.join(format!("{}.json.gz", name)))
+    }
+
+    /// The cassette a request chose with the cassette header, or the default
+    /// recording.
+    pub async fn select(&self, caller: &Caller) -> Result<Arc<Mutex<RecordedStreams>>, Status> {
+        match caller.headers.get(&self.header) {
+            Some(name) => self.get(name).await,
+            None => Ok(self.default_cassette()),
+        }
+    }
+
+    /// The named cassette, loaded from the cassette directory on first use.
+    /// A cassette without a file starts out empty, ready to be recorded into.
+    pub async fn get(&self, name: &str) -> Result<Arc<Mutex<RecordedStreams>>, Status> {
+        if !valid_name(name) {
+            return Err(Status::invalid_argument(format!(
+                "invalid cassette name '{}'",
+                name
+            )));
+        }
+        let path = self.path(name).ok_or_else(|| {
+            Status::failed_precondition(format!(
+                "cassette '{}' requested, but no --cassette-dir is set",
+                name
+            ))
+        })?;
+        if let Some(cassette) = self.named.lock().await.get(name) {
+            return Ok(cassette.clone());
+        }
+        // Cassettes are read off the runtime and without holding `named`, so
+        // requests for cassettes already loaded are not held up meanwhile.
+        let recorded = {
+            let name = name.to_string();
+            tokio::task::spawn_blocking(move || -> Result<RecordedStreams, Status> {
+                if !path.exists() {
+                    log::info!("new cassette '{}' at {}", name, path.display());
+                    return Ok(RecordedStreams::default());
+                }
+                let loaded = RecordedStreams::load(&path.to_string_lossy())
+                    .map_err(|e| Status::internal(e.to_string()))?;
+                log::info!("loaded cassette '{}' from {}", name, path.display());
+                Ok(loaded)
+            })
+            .await
+            .map_err(|e| Status::internal(e.to_string()))??
+        };
+        // Another request may have loaded the cassette meanwhile; the first
+        // one loaded is kept, so every request shares its replay cursors.
+        let mut named = self.named.lock().await;
+        let cassette = named
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(recorded)));
+        Ok(cassette.clone())
+    }
+
+    /// Whether the mock serves `model`: it is one of the models the mock was
+    /// built for or was given with `--model`, or is in the default recording
+    /// or a loaded cassette.
+    pub async fn knows(&self, model: &str) -> bool {
+        if crate::MODELS.contains(&model)
+            || self.models.iter().any(|known| known == model)
+            || self.default.lock().await.model_map.contains_key(model)
+        {
+            return true;
+        }
+        for cassette in self.named.lock().await.values() {
+            if cassette.lock().await.model_map.contains_key(model) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// The names of the cassettes loaded so far.
+    pub async fn loaded(&self) -> Vec<String> {
+        self.named.lock().await.keys().cloned().collect()
+    }
+
+    /// The names of the loaded cassettes recorded into since they were loaded
+    /// or last saved.
+    pub async fn unsaved(&self) -> Vec<String> {
+        let mut unsaved = Vec::new();
+        for (name, cassette) in self.named.lock().await.iter() {
+            if cassette.lock().await.dirty {
+                unsaved.push(name.clone());
+            }
+        }
+        unsaved
+    }
+
+    /// Writes every loaded cassette recorded into since it was loaded or last
+    /// saved back to its file.
+    pub async fn save_all(&self) -> Result<(), Box<dyn Error>> {
+        let named: Vec<(String, Arc<Mutex<RecordedStreams>>)> = self
+            .named
+            .lock()
+            .await
+            .iter()
+            .map(|(name, cassette)| (name.clone(), cassette.clone()))
+            .collect();
+        for (name, cassette) in named {
+            let mut cassette = cassette.lock().await;
+            if !cassette.dirty {
+                continue;
+            }
+            if let Some(path) = self.path(&name) {
+                cassette.save(&path.to_string_lossy())?;
+                cassette.dirty = false;
+                log::info!("saved cassette '{}' to {}", name, path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An output tensor of a recorded response, held in the little-endian layout
+/// of `raw_output_contents`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tensor {
+    pub name: String,
+    pub datatype: String,
+    pub shape: Vec<i64>,
+    pub raw: Vec<u8>,
+}
+
+impl Tensor {
+    /// A tensor from (possibly nested) data in the JSON layout of the HTTP
+    /// frontend, e.g. `vec![0.5, 1.5]` for `FP32` or `["hi"]` for `BYTES`,
+    /// holding as many elements as `shape` calls for.
+    pub fn new(
+        name: impl Into<String>,
+        datatype: impl Into<String>,
+        shape: impl Into<Vec<i64>>,
+        data: impl serde::Serialize,
+    ) -> Result<Self, String> {
+        let (name, datatype, shape) = (name.into(), datatype.into(), shape.into());
+        let data = serde_json::to_value(data).map_err(|e| e.to_string())?;
+        let mut values = Vec::new();
+        crate::tensor::flatten(&data, &mut values);
+        crate::tensor::check_count(&shape, values.len())
+            .map_err(|e| format!("tensor '{}': {}", name, e))?;
+        let raw = crate::tensor::json_to_raw(&datatype, &data)
+            .map_err(|e| format!("tensor '{}': {}", name, e))?;
+        Ok(Tensor {
+            name,
+            datatype,
+            shape,
+            raw,
+        })
+    }
+
+    /// A tensor holding typed `values`, the way to build `FP16` and `BF16`
+    /// outputs, which have no JSON layout.
+    pub fn from_values<T: crate::tensor::Element>(
+        name: impl Into<String>,
+        shape: impl Into<Vec<i64>>,
+        values: &[T],
+    ) -> Result<Self, String> {
+        let (name, shape) = (name.into(), shape.into());
+        let raw = crate::tensor::to_raw(&shape, values)
+            .map_err(|e| format!("tensor '{}': {}", name, e))?;
+        Ok(Tensor {
+            name,
+            datatype: T::DATATYPE.to_string(),
+            shape,
+            raw,
+        })
+    }
+
+    /// A tensor from its raw little-endian contents.
+    pub fn raw(
+        name: impl Into<String>,
+        datatype: impl Into<String>,
+        shape: impl Into<Vec<i64>>,
+        raw: Vec<u8>,
+    ) -> Result<Self, String> {
+        let (name, datatype, shape) = (name.into(), datatype.into(), shape.into());
+        let count = crate::tensor::element_count(&shape)
+            .map_err(|e| format!("tensor '{}': {}", name, e))?;
+        if let Some(size) = crate::tensor::element_size(&datatype) {
+            if raw.len() != count * size {
+                return Err(format!(
+                    "tensor '{}' of shape {:?} needs {} bytes of {}, got {}",
+                    name,
+                    shape,
+                    count * size,
+                    datatype,
+                    raw.len()
+                ));
+            }
+        }
+        Ok(Tensor {
+            name,
+            datatype,
+            shape,
+            raw,
+        })
+    }
+}
+
+fn infer_response(model: &str, version: &str, outputs: Vec<Tensor>) -> server::ModelInferResponse {
+    let mut resp = server::ModelInferResponse {
+        model_name: model.to_string(),
+        model_version: version.to_string(),
+        ..Default::default()
+    };
+    for tensor in outputs {
+        resp.outputs
+            .push(server::model_infer_response::InferOutputTensor {
+                name: tensor.name,
+                datatype: tensor.datatype,
+                shape: tensor.shape,
+                ..Default::default()
+            });
+        resp.raw_output_contents.push(tensor.raw);
+    }
+    resp
+}
+
+/// Builds a recording in code, in the format the mock records to disk, so
+/// fixtures can be made without a Triton and GPU at hand. Replay answers a
+/// model's requests with its responses in the order they were added,
+/// whatever the requests carry; stubs match on inputs instead.
+#[derive(Debug, Default)]
+pub struct CassetteBuilder {
+    recording: RecordedStreams,
+}
+
+impl CassetteBuilder {
+    pub fn new() -> Self {
+        CassetteBuilder::default()
+    }
+
+    fn stream(&mut self, model: &str, version: &str) -> &mut crate::RecordedStream {
+        self.recording
+            .model_map
+            .entry(model.to_string())
+            .or_default()
+            .stream_mut(Some(version))
+    }
+
+    /// Adds a model's config, answering one `model_config` request for
+    /// `version` (empty for requests without one). The config also gives
+    /// replay the model's version policy and the HTTP and OpenAI frontends
+    /// its tensor datatypes.
+    pub fn config(mut self, version: &str, config: server::ModelConfig) -> Self {
+        let model = config.name.clone();
+        let request = server::ModelConfigRequest {
+            name: model.clone(),
+            version: version.to_string(),
+        };
+        let response = server::ModelConfigResponse {
+            config: Some(config),
+        };
+        self.stream(&model, version)
+            .model_config
+            .entry(serde_json::to_string(&request).unwrap())
+            .or_default()
+            .push_back(serde_json::to_string(&response).unwrap());
+        self
+    }
+
+    /// Adds a `model_infer` request with its `inputs` and the response to it,
+    /// which answers the next `model_infer` request for the model. Replay
+    /// answers requests in order without looking at their inputs, so the
+    /// request is kept only for reference; stubs match on inputs instead.
+    pub fn infer(
+        mut self,
+        model: &str,
+        version: &str,
+        inputs: Vec<Tensor>,
+        outputs: Vec<Tensor>,
+    ) -> Self {
+        let mut request = server::ModelInferRequest {
+            model_name: model.to_string(),
+            model_version: version.to_string(),
+            ..Default::default()
+        };
+        for tensor in inputs {
+            request
+                .inputs
+                .push(server::model_infer_request::InferInputTensor {
+                    name: tensor.name,
+                    datatype: tensor.datatype,
+                    shape: tensor.shape,
+                    ..Default::default()
+                });
+            request.raw_input_contents.push(tensor.raw);
+        }
+        let response = infer_response(model, version, outputs);
+        let stream = self.stream(model, version);
+        stream
+            .model_infer_requests
+            .push_back(serde_json::to_string(&request).unwrap());
+        stream
+            .model_infer
+            .push_back(serde_json::to_string(&response).unwrap());
+        self
+    }
+
+    /// Adds the responses to one decoupled request on a stream, each sent
+    /// `delay` after the previous request or response. On `model_stream_infer`
+    /// streams each response answers one request.
+    pub fn stream_session(
+        mut self,
+        model: &str,
+        version: &str,
+        responses: Vec<(std::time::Duration, Vec<Tensor>)>,
+    ) -> Self {
+        let count = responses.len();
+        for (index, (delay, outputs)) in responses.into_iter().enumerate() {
+            let mut response = infer_response(model, version, outputs);
+            response.parameters.insert(
+                crate::FINAL_RESPONSE.to_string(),
+                server::InferParameter {
+                    parameter_choice: Some(server::infer_parameter::ParameterChoice::BoolParam(
+                        index + 1 == count,
+                    )),
+                },
+            );
+            let response = server::ModelStreamInferResponse {
+                error_message: String::new(),
+                infer_response: Some(response),
+            };
+            let stream = self.stream(model, version);
+            stream
+                .model_stream_infer
+                .push_back(serde_json::to_string(&response).unwrap());
+            stream
+                .model_stream_infer_delays
+                .push_back(delay.as_micros() as u64);
+        }
+        self
+    }
+
+    /// The recording as stored on disk, for
+    /// [`MockServerBuilder::recording_bytes`](crate::MockServerBuilder::recording_bytes).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        use std::io::Write;
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&serde_json::to_vec(&self.recording).unwrap())
+            .and_then(|()| gz.finish())
+            .expect("writing to memory cannot fail")
+    }
+
+    /// Writes the recording to `path`, which can be loaded like any recording
+    /// made against a real Triton.
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.recording.save(path)
+    }
+}
@@ -0,0 +1,88 @@
+pub mod proto {
+    #![allow(clippy::all)]
+    tonic::include_proto!("grpc.health.v1");
+}
+
+use crate::{cassette::Cassettes, journal::Caller};
+
+use std::sync::Arc;
+
+use proto::{
+    health_check_response::ServingStatus, health_server::Health, HealthCheckRequest,
+    HealthCheckResponse,
+};
+
+const INFERENCE_SERVICE: &str = "inference.GRPCInferenceService";
+
+/// Implements `grpc.health.v1.Health` so standard gRPC probes can check the
+/// mock. The empty service name and the inference service report server
+/// readiness; any model name reports that model's readiness in the cassette
+/// the caller chose.
+#[derive(Debug, Default)]
+pub struct MockHealthService {
+    cassettes: Arc<Cassettes>,
+}
+
+impl MockHealthService {
+    pub fn new_with(cassettes: Arc<Cassettes>) -> Self {
+        MockHealthService { cassettes }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for MockHealthService {
+    async fn check(
+        &self,
+        request: tonic::Request<HealthCheckRequest>,
+    ) -> std::result::Result<tonic::Response<HealthCheckResponse>, tonic::Status> {
+        let caller = Caller::from_request(&request);
+        let service = &request.get_ref().service;
+        log::debug!("health check: '{}'", service);
+        let ready = if service.is_empty() || service == INFERENCE_SERVICE {
+            crate::server_is_ready(&self.cassettes).await
+        } else if self.cassettes.knows(service).await {
+            let recorded_streams = self.cassettes.select(&caller).await?;
+            let recorded_streams = recorded_streams.lock().await;
+            crate::model_is_ready(&recorded_streams, service, "")
+        } else {
+            return Err(tonic::Status::not_found(format!(
+                "unknown service: '{}'",
+                service
+            )));
+        };
+        let status = if ready {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        Ok(tonic::Response::new(HealthCheckResponse {
+            status: status.into(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn status(cassettes: &Arc<Cassettes>) -> i32 {
+        MockHealthService::new_with(cassettes.clone())
+            .check(tonic::Request::new(HealthCheckRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .status
+    }
+
+    #[tokio::test]
+    async fn serving_follows_the_recording_and_shutdown() {
+        let cassettes = Arc::new(Cassettes::default());
+        assert_eq!(status(&cassettes).await, ServingStatus::NotServing as i32);
+
+        cassettes.default_cassette().lock().await.loaded = true;
+        assert_eq!(status(&cassettes).await, ServingStatus::Serving as i32);
+
+        cassettes.close();
+        assert_eq!(status(&cassettes).await, ServingStatus::NotServing as i32);
+    }
+}
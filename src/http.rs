@@ -0,0 +1,708 @@
+use crate::{
+    journal::Caller,
+    listen::Peer,
+    server::{
+        self, grpc_inference_service_server::GrpcInferenceService, model_infer_request, DataType,
+    },
+    tensor, MockInferenceService,
+};
+
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{ConnectInfo, FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, HeaderName, StatusCode},
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+
+use serde_json::{json, Map, Value};
+
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+use tonic::transport::server::TcpConnectInfo;
+
+type Service = Arc<MockInferenceService>;
+
+type ModelPath = Path<HashMap<String, String>>;
+
+/// Maps a gRPC status onto the HTTP status Triton's HTTP frontend uses for
+/// the equivalent server error.
+fn http_status(code: tonic::Code) -> StatusCode {
+    match code {
+        tonic::Code::Ok => StatusCode::OK,
+        tonic::Code::NotFound => StatusCode::NOT_FOUND,
+        tonic::Code::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        tonic::Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        tonic::Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        _ => StatusCode::BAD_REQUEST,
+    }
+}
+
+pub fn error_response(status: tonic::Status) -> Response {
+    (
+        http_status(status.code()),
+        Json(json!({ "error": status.message() })),
+    )
+        .into_response()
+}
+
+/// What an HTTP request passes on to the service: its headers and the peer
+/// it came from.
+pub struct Metadata {
+    pub headers: HeaderMap,
+    peer: Option<SocketAddr>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Metadata {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Metadata {
+            headers: parts.headers.clone(),
+            peer: parts
+                .extensions
+                .get::<ConnectInfo<Peer>>()
+                .and_then(|ConnectInfo(Peer(peer))| *peer),
+        })
+    }
+}
+
+/// Wraps a message in a gRPC request carrying the HTTP request's headers as
+/// metadata and its peer as the remote address, so they reach the service as
+/// they would over gRPC.
+pub fn grpc_request<T>(metadata: &Metadata, message: T) -> tonic::Request<T> {
+    let mut request = tonic::Request::new(message);
+    request.extensions_mut().insert(TcpConnectInfo {
+        local_addr: None,
+        remote_addr: metadata.peer,
+    });
+    for (name, value) in &metadata.headers {
+        let (Ok(key), Ok(value)) = (
+            tonic::metadata::AsciiMetadataKey::from_bytes(name.as_str().as_bytes()),
+            tonic::metadata::AsciiMetadataValue::try_from(value.as_bytes()),
+        ) else {
+            continue;
+        };
+        request.metadata_mut().append(key, value);
+    }
+    request
+}
+
+fn bad_request(message: impl Into<String>) -> Response {
+    error_response(tonic::Status::invalid_argument(message))
+}
+
+fn model_and_version(params: &HashMap<String, String>) -> (String, String) {
+    (
+        params.get("model").cloned().unwrap_or_default(),
+        params.get("version").cloned().unwrap_or_default(),
+    )
+}
+
+async fn health_live(State(service): State<Service>, metadata: Metadata) -> Response {
+    match service
+        .server_live(grpc_request(&metadata, server::ServerLiveRequest {}))
+        .await
+    {
+        Ok(resp) if resp.get_ref().live => StatusCode::OK.into_response(),
+        Ok(_) => StatusCode::BAD_REQUEST.into_response(),
+        Err(status) => error_response(status),
+    }
+}
+
+async fn health_ready(State(service): State<Service>, metadata: Metadata) -> Response {
+    match service
+        .server_ready(grpc_request(&metadata, server::ServerReadyRequest {}))
+        .await
+    {
+        Ok(resp) if resp.get_ref().ready => StatusCode::OK.into_response(),
+        Ok(_) => StatusCode::BAD_REQUEST.into_response(),
+        Err(status) => error_response(status),
+    }
+}
+
+async fn model_ready(
+    State(service): State<Service>,
+    Path(params): ModelPath,
+    metadata: Metadata,
+) -> Response {
+    let (name, version) = model_and_version(&params);
+    match service
+        .model_ready(grpc_request(
+            &metadata,
+            server::ModelReadyRequest { name, version },
+        ))
+        .await
+    {
+        Ok(resp) if resp.get_ref().ready => StatusCode::OK.into_response(),
+        Ok(_) => StatusCode::BAD_REQUEST.into_response(),
+        Err(status) => error_response(status),
+    }
+}
+
+async fn model_metadata(
+    State(service): State<Service>,
+    Path(params): ModelPath,
+    metadata: Metadata,
+) -> Response {
+    let (name, version) = model_and_version(&params);
+    let resp = match service
+        .model_metadata(grpc_request(
+            &metadata,
+            server::ModelMetadataRequest { name, version },
+        ))
+        .await
+    {
+        Ok(resp) => resp.into_inner(),
+        Err(status) => return error_response(status),
+    };
+    let tensors = |tensors: &[server::model_metadata_response::TensorMetadata]| {
+        tensors
+            .iter()
+            .map(|t| json!({ "name": t.name, "datatype": t.datatype, "shape": t.shape }))
+            .collect::<Vec<_>>()
+    };
+    Json(json!({
+        "name": resp.name,
+        "versions": resp.versions,
+        "platform": resp.platform,
+        "inputs": tensors(&resp.inputs),
+        "outputs": tensors(&resp.outputs),
+    }))
+    .into_response()
+}
+
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Rewrites the serde form of a `ModelConfig` into the shape Triton's HTTP
+/// frontend returns: oneof wrappers are flattened into their field name and
+/// `data_type` enums are rendered by name.
+fn config_json(value: Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let mut out = Map::new();
+            for (key, value) in fields {
+                match value {
+                    Value::Object(choice) if key.ends_with("_choice") && choice.len() == 1 => {
+                        let (variant, inner) = choice.into_iter().next().unwrap();
+                        out.insert(snake_case(&variant), config_json(inner));
+                    }
+                    Value::Null if key.ends_with("_choice") => {}
+                    Value::Number(n) if key == "data_type" => {
+                        let name = n
+                            .as_i64()
+                            .and_then(|n| DataType::try_from(n as i32).ok())
+                            .unwrap_or(DataType::TypeInvalid)
+                            .as_str_name();
+                        out.insert(key, Value::from(name));
+                    }
+                    value => {
+                        out.insert(key, config_json(value));
+                    }
+                }
+            }
+            Value::Object(out)
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(config_json).collect()),
+        value => value,
+    }
+}
+
+async fn model_config(
+    State(service): State<Service>,
+    Path(params): ModelPath,
+    metadata: Metadata,
+) -> Response {
+    let (name, version) = model_and_version(&params);
+    match service
+        .model_config(grpc_request(
+            &metadata,
+            server::ModelConfigRequest { name, version },
+        ))
+        .await
+    {
+        Ok(resp) => {
+            let config = resp.into_inner().config.unwrap_or_default();
+            Json(config_json(serde_json::to_value(config).unwrap())).into_response()
+        }
+        Err(status) => error_response(status),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct InferInputJson {
+    name: String,
+    shape: Vec<i64>,
+    datatype: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+    data: Option<Value>,
+}
+
+#[derive(serde::Deserialize)]
+struct InferOutputJson {
+    name: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+}
+
+#[derive(serde::Deserialize)]
+struct InferRequestJson {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+    inputs: Vec<InferInputJson>,
+    #[serde(default)]
+    outputs: Vec<InferOutputJson>,
+}
+
+/// Header carrying the length of the JSON part of a request or response that
+/// uses the binary tensor data extension.
+const INFERENCE_HEADER_CONTENT_LENGTH: &str = "inference-header-content-length";
+
+/// Which outputs the client asked to receive as binary data, taken from the
+/// request-level `binary_data_output` and per-output `binary_data` parameters.
+#[derive(Debug, Default)]
+struct BinaryOutputs {
+    default: bool,
+    outputs: HashMap<String, bool>,
+}
+
+impl BinaryOutputs {
+    fn is_binary(&self, name: &str) -> bool {
+        self.outputs.get(name).copied().unwrap_or(self.default)
+    }
+}
+
+fn take_bool_param(params: &mut Map<String, Value>, key: &str) -> Result<Option<bool>, String> {
+    match params.remove(key) {
+        Some(Value::Bool(v)) => Ok(Some(v)),
+        Some(v) => Err(format!(
+            "expected boolean for '{}' parameter, got {}",
+            key, v
+        )),
+        None => Ok(None),
+    }
+}
+
+fn infer_request(
+    name: String,
+    version: String,
+    mut body: InferRequestJson,
+    binary: &[u8],
+) -> Result<(server::ModelInferRequest, BinaryOutputs), String> {
+    let mut binary_outputs = BinaryOutputs {
+        default: take_bool_param(&mut body.parameters, "binary_data_output")?.unwrap_or(false),
+        ..Default::default()
+    };
+    let mut request = server::ModelInferRequest {
+        model_name: name,
+        model_version: version,
+        id: body.id,
+        parameters: tensor::json_to_parameters(&body.parameters)?,
+        ..Default::default()
+    };
+    let mut binary = binary;
+    for mut input in body.inputs {
+        let binary_size = match input.parameters.remove("binary_data_size") {
+            Some(size) => Some(size.as_u64().ok_or_else(|| {
+                format!(
+                    "input '{}': expected unsigned integer for 'binary_data_size' parameter",
+                    input.name
+                )
+            })? as usize),
+            None => None,
+        };
+        match (binary_size, &input.data) {
+            (Some(_), Some(_)) => {
+                return Err(format!(
+                    "input '{}': 'data' and 'binary_data_size' are mutually exclusive",
+                    input.name
+                ))
+            }
+            (Some(size), None) => {
+                if binary.len() < size {
+                    return Err(format!(
+                        "input '{}': unexpected end of binary data, expected {} more bytes",
+                        input.name, size
+                    ));
+                }
+                let (data, rest) = binary.split_at(size);
+                request.raw_input_contents.push(data.to_vec());
+                binary = rest;
+            }
+            (None, Some(data)) => {
+                let raw = tensor::json_to_raw(&input.datatype, data)
+                    .map_err(|e| format!("input '{}': {}", input.name, e))?;
+                request.raw_input_contents.push(raw);
+            }
+            (None, None) => {}
+        }
+        request.inputs.push(model_infer_request::InferInputTensor {
+            name: input.name,
+            datatype: input.datatype,
+            shape: input.shape,
+            parameters: tensor::json_to_parameters(&input.parameters)?,
+            contents: None,
+        });
+    }
+    if !binary.is_empty() {
+        return Err(format!(
+            "{} bytes of binary data not consumed by any input",
+            binary.len()
+        ));
+    }
+    for mut output in body.outputs {
+        if let Some(is_binary) = take_bool_param(&mut output.parameters, "binary_data")? {
+            binary_outputs
+                .outputs
+                .insert(output.name.clone(), is_binary);
+        }
+        request
+            .outputs
+            .push(model_infer_request::InferRequestedOutputTensor {
+                name: output.name,
+                parameters: tensor::json_to_parameters(&output.parameters)?,
+            });
+    }
+    Ok((request, binary_outputs))
+}
+
+/// Builds the JSON response, appending the outputs requested as binary data to
+/// the returned buffer in output order.
+fn infer_response(
+    resp: &server::ModelInferResponse,
+    binary_outputs: &BinaryOutputs,
+) -> Result<(Value, Vec<u8>), String> {
+    let mut outputs = Vec::with_capacity(resp.outputs.len());
+    let mut binary = Vec::new();
+    for (index, output) in resp.outputs.iter().enumerate() {
+        let mut json = json!({
+            "name": output.name,
+            "datatype": output.datatype,
+            "shape": output.shape,
+        });
+        let mut parameters = tensor::parameters_to_json(&output.parameters);
+        let raw = resp
+            .raw_output_contents
+            .get(index)
+            .filter(|raw| !raw.is_empty());
+        if binary_outputs.is_binary(&output.name) {
+            let data = match (&output.contents, raw) {
+                (Some(contents), _) => {
+                    let values = tensor::contents_to_json(&output.datatype, contents);
+                    tensor::json_to_raw(&output.datatype, &Value::Array(values))
+                        .map_err(|e| format!("output '{}': {}", output.name, e))?
+                }
+                (None, Some(raw)) => raw.clone(),
+                (None, None) => Vec::new(),
+            };
+            if output.contents.is_some() || raw.is_some() {
+                parameters.insert("binary_data_size".to_string(), Value::from(data.len()));
+                binary.extend_from_slice(&data);
+            }
+        } else if let Some(contents) = &output.contents {
+            json["data"] = Value::Array(tensor::contents_to_json(&output.datatype, contents));
+        } else if let Some(raw) = raw {
+            let data = tensor::raw_to_json(&output.datatype, raw)
+                .map_err(|e| format!("output '{}': {}", output.name, e))?;
+            json["data"] = Value::Array(data);
+        }
+        if !parameters.is_empty() {
+            json["parameters"] = Value::Object(parameters);
+        }
+        outputs.push(json);
+    }
+    let mut json = json!({
+        "model_name": resp.model_name,
+        "model_version": resp.model_version,
+        "outputs": outputs,
+    });
+    if !resp.id.is_empty() {
+        json["id"] = Value::from(resp.id.as_str());
+    }
+    if !resp.parameters.is_empty() {
+        json["parameters"] = Value::Object(tensor::parameters_to_json(&resp.parameters));
+    }
+    Ok((json, binary))
+}
+
+/// Splits a request body into its JSON header and the binary tensor data
+/// that follows it, as described by `Inference-Header-Content-Length`.
+fn split_body<'a>(headers: &HeaderMap, body: &'a [u8]) -> Result<(&'a [u8], &'a [u8]), String> {
+    let Some(length) = headers.get(INFERENCE_HEADER_CONTENT_LENGTH) else {
+        return Ok((body, &[]));
+    };
+    let length = length
+        .to_str()
+        .ok()
+        .and_then(|length| length.parse::<usize>().ok())
+        .ok_or_else(|| format!("invalid {} header", INFERENCE_HEADER_CONTENT_LENGTH))?;
+    if length > body.len() {
+        return Err(format!(
+            "{} ({}) exceeds the request body size ({})",
+            INFERENCE_HEADER_CONTENT_LENGTH,
+            length,
+            body.len()
+        ));
+    }
+    Ok(body.split_at(length))
+}
+
+async fn model_infer(
+    State(service): State<Service>,
+    Path(params): ModelPath,
+    metadata: Metadata,
+    body: Bytes,
+) -> Response {
+    let (name, version) = model_and_version(&params);
+    let (header, binary) = match split_body(&metadata.headers, &body) {
+        Ok(parts) => parts,
+        Err(e) => return bad_request(e),
+    };
+    let body: InferRequestJson = match serde_json::from_slice(header) {
+        Ok(body) => body,
+        Err(e) => return bad_request(format!("failed to parse the request JSON: {}", e)),
+    };
+    let (request, binary_outputs) = match infer_request(name, version, body, binary) {
+        Ok(request) => request,
+        Err(e) => return bad_request(e),
+    };
+    let resp = match service.model_infer(grpc_request(&metadata, request)).await {
+        Ok(resp) => resp.into_inner(),
+        Err(status) => return error_response(status),
+    };
+    // The response was recorded or stubbed, so failing to convert it is the
+    // mock's fault rather than the client's.
+    let (json, binary) = match infer_response(&resp, &binary_outputs) {
+        Ok(parts) => parts,
+        Err(e) => return error_response(tonic::Status::internal(e)),
+    };
+    if binary.is_empty() {
+        return Json(json).into_response();
+    }
+    let mut body = serde_json::to_vec(&json).unwrap();
+    let header_length = body.len();
+    body.extend_from_slice(&binary);
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (
+                HeaderName::from_static(INFERENCE_HEADER_CONTENT_LENGTH),
+                header_length.to_string(),
+            ),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Maps the fields of a generate request onto input tensors, taking their
+/// datatypes from the recorded model config when it is known. `id` and
+/// `parameters` become the request's id and parameters.
+pub fn generate_request(
+    name: String,
+    version: String,
+    mut body: Map<String, Value>,
+    config: Option<server::ModelConfig>,
+) -> Result<server::ModelInferRequest, String> {
+    let id = match body.remove("id") {
+        Some(Value::String(id)) => id,
+        Some(id) => return Err(format!("expected string for 'id', got {}", id)),
+        None => String::new(),
+    };
+    let parameters = match body.remove("parameters") {
+        Some(Value::Object(parameters)) => tensor::json_to_parameters(&parameters)?,
+        Some(parameters) => {
+            return Err(format!(
+                "expected object for 'parameters', got {}",
+                parameters
+            ))
+        }
+        None => HashMap::new(),
+    };
+    let batched = config.as_ref().is_some_and(|c| c.max_batch_size > 0);
+    let mut request = server::ModelInferRequest {
+        model_name: name,
+        model_version: version,
+        id,
+        parameters,
+        ..Default::default()
+    };
+    for (name, value) in body {
+        let datatype = config
+            .as_ref()
+            .and_then(|c| c.input.iter().find(|input| input.name == name))
+            .map(|input| tensor::config_datatype(input.data_type))
+            .unwrap_or_else(|| {
+                let first = match &value {
+                    Value::Array(values) => values.first().unwrap_or(&Value::Null),
+                    value => value,
+                };
+                match first {
+                    Value::Bool(_) => "BOOL",
+                    Value::Number(n) if n.is_f64() => "FP32",
+                    Value::Number(_) => "INT64",
+                    _ => "BYTES",
+                }
+                .to_string()
+            });
+        let count = match &value {
+            Value::Array(values) => values.len() as i64,
+            _ => 1,
+        };
+        let raw = tensor::json_to_raw(&datatype, &value)
+            .map_err(|e| format!("input '{}': {}", name, e))?;
+        request.raw_input_contents.push(raw);
+        request.inputs.push(model_infer_request::InferInputTensor {
+            name,
+            datatype,
+            shape: if batched { vec![1, count] } else { vec![count] },
+            ..Default::default()
+        });
+    }
+    Ok(request)
+}
+
+/// Flattens an inference response into the generate response format, with
+/// each output as a top-level field holding a scalar when it has one element.
+fn generate_response(resp: &server::ModelInferResponse) -> Result<Value, String> {
+    let (infer, _) = infer_response(resp, &BinaryOutputs::default())?;
+    let mut json = json!({
+        "model_name": resp.model_name,
+        "model_version": resp.model_version,
+    });
+    if !resp.id.is_empty() {
+        json["id"] = Value::from(resp.id.as_str());
+    }
+    for output in infer["outputs"].as_array().into_iter().flatten() {
+        let name = output["name"].as_str().unwrap_or_default();
+        json[name] = match output.get("data") {
+            Some(Value::Array(data)) if data.len() == 1 => data[0].clone(),
+            Some(data) => data.clone(),
+            None => Value::Null,
+        };
+    }
+    Ok(json)
+}
+
+async fn parse_generate(
+    service: &MockInferenceService,
+    params: &HashMap<String, String>,
+    metadata: &Metadata,
+    body: &[u8],
+) -> Result<server::ModelInferRequest, Response> {
+    let (name, version) = model_and_version(params);
+    let body: Map<String, Value> = serde_json::from_slice(body)
+        .map_err(|e| bad_request(format!("failed to parse the request JSON: {}", e)))?;
+    let caller = Caller::from_request(&grpc_request(metadata, ()));
+    let config = service.recorded_config(&caller, &name, &version).await;
+    generate_request(name, version, body, config).map_err(bad_request)
+}
+
+async fn generate(
+    State(service): State<Service>,
+    Path(params): ModelPath,
+    metadata: Metadata,
+    body: Bytes,
+) -> Response {
+    let request = match parse_generate(&service, &params, &metadata, &body).await {
+        Ok(request) => request,
+        Err(resp) => return resp,
+    };
+    let resp = match service.model_infer(grpc_request(&metadata, request)).await {
+        Ok(resp) => resp.into_inner(),
+        Err(status) => return error_response(status),
+    };
+    match generate_response(&resp) {
+        Ok(json) => Json(json).into_response(),
+        Err(e) => error_response(tonic::Status::internal(e)),
+    }
+}
+
+/// Streams every response of a decoupled request as a server-sent event; a
+/// failure is sent as a final `error` event.
+async fn generate_stream(
+    State(service): State<Service>,
+    Path(params): ModelPath,
+    metadata: Metadata,
+    body: Bytes,
+) -> Response {
+    let request = match parse_generate(&service, &params, &metadata, &body).await {
+        Ok(request) => request,
+        Err(resp) => return resp,
+    };
+    let responses = match service
+        .decoupled_infer(grpc_request(&metadata, request))
+        .await
+    {
+        Ok(responses) => responses,
+        Err(status) => return error_response(status),
+    };
+    let events = ReceiverStream::new(responses).map(|resp| {
+        let json = resp
+            .map_err(|status| status.message().to_string())
+            .and_then(|resp| generate_response(&resp.infer_response.unwrap_or_default()));
+        let data = json.unwrap_or_else(|e| json!({ "error": e }));
+        Ok::<_, Infallible>(Event::default().data(data.to_string()))
+    });
+    Sse::new(events).into_response()
+}
+
+/// The KServe v2 REST routes, backed by the same service as the gRPC
+/// listeners so both protocols share one recording.
+pub fn router(service: Service) -> Router {
+    Router::new()
+        .route("/v2/health/live", get(health_live))
+        .route("/v2/health/ready", get(health_ready))
+        .route("/v2/models/:model", get(model_metadata))
+        .route("/v2/models/:model/ready", get(model_ready))
+        .route("/v2/models/:model/config", get(model_config))
+        .route("/v2/models/:model/infer", post(model_infer))
+        .route("/v2/models/:model/generate", post(generate))
+        .route("/v2/models/:model/generate_stream", post(generate_stream))
+        .route("/v2/models/:model/versions/:version", get(model_metadata))
+        .route(
+            "/v2/models/:model/versions/:version/ready",
+            get(model_ready),
+        )
+        .route(
+            "/v2/models/:model/versions/:version/config",
+            get(model_config),
+        )
+        .route(
+            "/v2/models/:model/versions/:version/infer",
+            post(model_infer),
+        )
+        .route(
+            "/v2/models/:model/versions/:version/generate",
+            post(generate),
+        )
+        .route(
+            "/v2/models/:model/versions/:version/generate_stream",
+            post(generate_stream),
+        )
+        .with_state(service)
+}
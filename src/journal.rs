@@ -0,0 +1,239 @@
+use crate::{server, tensor};
+
+use std::collections::{BTreeMap, VecDeque};
+
+use serde_json::{json, Value};
+
+/// Where the response to a journaled request came from.
+#[derive(serde::Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "source", rename_all = "lowercase")]
+pub enum Outcome {
+    /// The recorded response at `index` in its list of responses.
+    Recording {
+        index: usize,
+    },
+    Stub {
+        id: u64,
+    },
+    Upstream,
+    /// Fabricated from the model config by `--synthetic`.
+    Synthetic,
+    /// Readiness or metadata derived from the recorded model config, or
+    /// readiness set by a scenario step.
+    Config,
+    /// Nothing answered the request.
+    #[default]
+    Miss,
+}
+
+impl Outcome {
+    fn source(&self) -> &'static str {
+        match self {
+            Outcome::Recording { .. } => "recording",
+            Outcome::Stub { .. } => "stub",
+            Outcome::Upstream => "upstream",
+            Outcome::Synthetic => "synthetic",
+            Outcome::Config => "config",
+            Outcome::Miss => "miss",
+        }
+    }
+}
+
+/// Who sent a request: the peer address and the gRPC metadata (or HTTP
+/// headers) it carried.
+#[derive(Clone, Debug, Default)]
+pub struct Caller {
+    pub peer: Option<String>,
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Caller {
+    pub fn from_request<T>(request: &tonic::Request<T>) -> Self {
+        Caller {
+            peer: request.remote_addr().map(|addr| addr.to_string()),
+            headers: request
+                .metadata()
+                .iter()
+                .filter_map(|entry| match entry {
+                    tonic::metadata::KeyAndValueRef::Ascii(key, value) => Some((
+                        key.to_string(),
+                        value.to_str().unwrap_or_default().to_string(),
+                    )),
+                    tonic::metadata::KeyAndValueRef::Binary(..) => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub timestamp: String,
+    pub rpc: &'static str,
+    pub model: String,
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub request: Value,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JournalEntry {
+    pub fn new(
+        rpc: &'static str,
+        model: &str,
+        version: &str,
+        caller: Caller,
+        request: Value,
+    ) -> Self {
+        JournalEntry {
+            seq: 0,
+            timestamp: crate::logging::timestamp_now(),
+            rpc,
+            model: model.to_string(),
+            version: version.to_string(),
+            peer: caller.peer,
+            headers: caller.headers,
+            request,
+            outcome: Outcome::Miss,
+            error: None,
+        }
+    }
+
+    pub fn finished(mut self, outcome: Outcome, error: Option<&tonic::Status>) -> Self {
+        self.outcome = outcome;
+        self.error = error.map(|status| status.message().to_string());
+        self
+    }
+
+    /// The size of the first dimension of the first input, which is the batch
+    /// size for models that batch.
+    fn batch_size(&self) -> Option<i64> {
+        self.request["inputs"][0]["shape"][0].as_i64()
+    }
+}
+
+/// Decodes an inference request into the JSON layout of the HTTP frontend,
+/// so journaled requests can be inspected and filtered on.
+pub fn infer_request_json(request: &server::ModelInferRequest) -> Value {
+    let inputs: Vec<Value> = request
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(index, input)| {
+            json!({
+                "name": input.name,
+                "datatype": input.datatype,
+                "shape": input.shape,
+                "parameters": tensor::parameters_to_json(&input.parameters),
+                "data": tensor::input_to_json(request, index).ok(),
+            })
+        })
+        .collect();
+    let outputs: Vec<&str> = request
+        .outputs
+        .iter()
+        .map(|output| output.name.as_str())
+        .collect();
+    json!({
+        "id": request.id,
+        "parameters": tensor::parameters_to_json(&request.parameters),
+        "inputs": inputs,
+        "outputs": outputs,
+    })
+}
+
+/// Which journal entries to return. Every field that is set must match.
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct JournalFilter {
+    pub model: Option<String>,
+    pub version: Option<String>,
+    pub rpc: Option<String>,
+    /// `recording`, `stub`, `upstream`, `synthetic`, `config` or `miss`.
+    pub source: Option<String>,
+    pub batch_size: Option<i64>,
+    /// A header the request carried, as `<name>:<value>`.
+    pub header: Option<String>,
+    /// Only entries after this sequence number.
+    pub after: Option<u64>,
+}
+
+impl JournalFilter {
+    fn matches(&self, entry: &JournalEntry) -> bool {
+        self.model
+            .as_ref()
+            .is_none_or(|model| *model == entry.model)
+            && self
+                .version
+                .as_ref()
+                .is_none_or(|version| *version == entry.version)
+            && self.rpc.as_ref().is_none_or(|rpc| rpc == entry.rpc)
+            && self
+                .source
+                .as_ref()
+                .is_none_or(|source| source == entry.outcome.source())
+            && self
+                .batch_size
+                .is_none_or(|batch_size| entry.batch_size() == Some(batch_size))
+            && self.header.as_ref().is_none_or(|header| {
+                let (name, value) = header.split_once(':').unwrap_or((header, ""));
+                entry
+                    .headers
+                    .get(&name.trim().to_ascii_lowercase())
+                    .is_some_and(|v| value.is_empty() || v == value.trim())
+            })
+            && self.after.is_none_or(|after| entry.seq > after)
+    }
+}
+
+/// The most recent requests the mock received, oldest first, bounded to
+/// `capacity` entries.
+#[derive(Debug)]
+pub struct Journal {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+    next_seq: u64,
+}
+
+impl Default for Journal {
+    fn default() -> Self {
+        Journal::new(1000)
+    }
+}
+
+impl Journal {
+    pub fn new(capacity: usize) -> Self {
+        Journal {
+            entries: VecDeque::new(),
+            capacity,
+            next_seq: 0,
+        }
+    }
+
+    pub fn record(&mut self, mut entry: JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.next_seq += 1;
+        entry.seq = self.next_seq;
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn find(&self, filter: &JournalFilter) -> Vec<&JournalEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| filter.matches(entry))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
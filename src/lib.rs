@@ -0,0 +1,2026 @@
+#![allow(clippy::result_large_err)]
+
+/// The Triton inference protocol, generated from its protobuf definitions.
+pub mod server {
+    #![allow(clippy::all)]
+    tonic::include_proto!("inference");
+
+    /// Descriptors of every compiled proto, served by the reflection service.
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("triton_mock_descriptor");
+}
+
+mod admin;
+mod cassette;
+mod health;
+mod http;
+mod journal;
+mod listen;
+mod logging;
+mod metrics;
+mod mock_server;
+mod openai;
+mod scenario;
+mod shared_memory;
+mod stubs;
+mod synthetic;
+/// Conversions between tensors as the Triton protocol carries them and JSON
+/// or typed Rust values.
+pub mod tensor;
+mod trace;
+mod upstream;
+
+pub use cassette::{CassetteBuilder, Tensor};
+pub use listen::ListenAddress;
+pub use mock_server::{MockServer, MockServerBuilder, ShutdownHandle};
+pub use synthetic::Fill;
+pub use upstream::Mode;
+
+/// Installs the mock's logger, configured from the environment and adjustable
+/// through the `log_settings` RPC. Panics if a logger is already installed.
+pub fn init_logging() {
+    logging::init();
+}
+
+use server::{
+    grpc_inference_service_client::GrpcInferenceServiceClient,
+    grpc_inference_service_server::{GrpcInferenceService, GrpcInferenceServiceServer},
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    error::Error,
+    pin::Pin,
+    sync::Arc,
+};
+
+use tonic::{
+    codec::CompressionEncoding,
+    transport::{Certificate, Channel, ClientTlsConfig, Identity, ServerTlsConfig},
+    Status,
+};
+
+use tokio::sync::Mutex;
+
+use tokio_stream::Stream;
+
+use cassette::Cassettes;
+use journal::{Caller, Journal, JournalEntry, Outcome};
+use metrics::{InferTimings, Metrics};
+use scenario::{Gate, Scenarios};
+use shared_memory::SharedMemoryRegions;
+use stubs::{Rpc, Stubs};
+use trace::TraceManager;
+use upstream::Upstream;
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Debug)]
+struct RecordedStream {
+    model_config: BTreeMap<String, VecDeque<String>>,
+    model_infer: VecDeque<String>,
+    /// The requests the `model_infer` responses answered, where the
+    /// recording was built with them. Replay does not match on them.
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    model_infer_requests: VecDeque<String>,
+    model_stream_infer: VecDeque<String>,
+    /// Microseconds between each `model_stream_infer` response and the
+    /// previous request or response on its stream.
+    #[serde(default, skip_serializing_if = "VecDeque::is_empty")]
+    model_stream_infer_delays: VecDeque<u64>,
+}
+
+/// Recordings for one model. Traffic that carried no version lives in the
+/// flattened unversioned stream, which keeps recordings made before versions
+/// were tracked loadable; everything else is kept per version.
+#[derive(serde::Deserialize, serde::Serialize, Default, Debug)]
+struct RecordedModel {
+    #[serde(flatten)]
+    unversioned: RecordedStream,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    versions: BTreeMap<String, RecordedStream>,
+    #[serde(skip)]
+    model_stream_infer_inputs: VecDeque<String>,
+    /// How far replay has got through each list of recorded responses, per
+    /// session (`None` for requests without a session header) and version;
+    /// replay never removes responses from the recording itself.
+    #[serde(skip)]
+    cursors: HashMap<(Option<String>, Option<String>, Track), usize>,
+}
+
+/// A list of recorded responses replayed in order: the `model_infer` or
+/// `model_stream_infer` responses of a version, or the `model_config`
+/// responses to one request.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Track {
+    Infer,
+    StreamInfer,
+    Config(String),
+}
+
+impl RecordedModel {
+    fn version_policy(&self) -> Option<server::model_version_policy::PolicyChoice> {
+        std::iter::once(&self.unversioned)
+            .chain(self.versions.values())
+            .flat_map(|stream| stream.model_config.values().flatten())
+            .filter_map(|json| serde_json::from_str::<server::ModelConfigResponse>(json).ok())
+            .find_map(|resp| resp.config?.version_policy?.policy_choice)
+    }
+
+    /// The recorded versions that the model's version policy would have
+    /// loaded, in ascending order.
+    fn loaded_versions(&self) -> Vec<i64> {
+        use server::model_version_policy::PolicyChoice;
+
+        let mut versions: Vec<i64> = self
+            .versions
+            .keys()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        versions.sort_unstable();
+        match self.version_policy() {
+            Some(PolicyChoice::Latest(latest)) => {
+                let keep = (latest.num_versions as usize).max(1);
+                versions.split_off(versions.len().saturating_sub(keep))
+            }
+            Some(PolicyChoice::Specific(specific)) => versions
+                .into_iter()
+                .filter(|v| specific.versions.contains(v))
+                .collect(),
+            Some(PolicyChoice::All(_)) | None => versions,
+        }
+    }
+
+    /// Resolves a requested version to the version whose recordings should be
+    /// used, following Triton: an empty version means the latest loaded one.
+    /// `None` selects the unversioned recordings.
+    fn resolve_version(&self, name: &str, version: &str) -> Result<Option<String>, Status> {
+        if self.versions.is_empty() {
+            return Ok(None);
+        }
+        if version.is_empty() {
+            return Ok(self.loaded_versions().last().map(|v| v.to_string()));
+        }
+        let loaded = version
+            .parse::<i64>()
+            .map(|v| self.loaded_versions().contains(&v))
+            .unwrap_or(false);
+        if loaded {
+            Ok(Some(version.to_string()))
+        } else {
+            Err(Status::not_found(format!(
+                "Request for unknown model: '{}' version {} is not found",
+                name, version
+            )))
+        }
+    }
+
+    fn is_ready(&self, version: &str) -> bool {
+        version.is_empty()
+            || self.versions.is_empty()
+            || version
+                .parse::<i64>()
+                .map(|v| self.loaded_versions().contains(&v))
+                .unwrap_or(false)
+    }
+
+    /// The first recorded config for `version`, falling back to one recorded
+    /// without a version.
+    fn config(&self, version: Option<&str>) -> Option<server::ModelConfig> {
+        version
+            .and_then(|version| self.versions.get(version))
+            .into_iter()
+            .chain(std::iter::once(&self.unversioned))
+            .flat_map(|stream| stream.model_config.values().flatten())
+            .filter_map(|json| serde_json::from_str::<server::ModelConfigResponse>(json).ok())
+            .find_map(|resp| resp.config)
+    }
+
+    fn stream(&self, version: Option<&str>) -> Option<&RecordedStream> {
+        match version {
+            Some(version) if !version.is_empty() => self.versions.get(version),
+            _ => Some(&self.unversioned),
+        }
+    }
+
+    /// The next response of `track` not yet replayed in `session`, with its
+    /// index in the recording. A session starts from the first response the
+    /// first time it is seen.
+    fn next_response(
+        &mut self,
+        session: Option<&str>,
+        version: Option<&str>,
+        track: Track,
+    ) -> Option<(usize, String)> {
+        let stream = self.stream(version)?;
+        let key = (
+            session.map(str::to_string),
+            version.filter(|v| !v.is_empty()).map(str::to_string),
+            track,
+        );
+        let index = self.cursors.get(&key).copied().unwrap_or(0);
+        let json = match &key.2 {
+            Track::Infer => stream.model_infer.get(index),
+            Track::StreamInfer => stream.model_stream_infer.get(index),
+            Track::Config(request) => stream.model_config.get(request)?.get(index),
+        }?
+        .clone();
+        self.cursors.insert(key, index + 1);
+        Some((index, json))
+    }
+
+    /// The next response of `track` as a scenario's `gate` allows it: from
+    /// the index the scenario step names, a failure, or none at all.
+    fn gated_response(
+        &mut self,
+        gate: &Gate,
+        session: Option<&str>,
+        version: Option<&str>,
+        track: Track,
+    ) -> Result<Option<(usize, String)>, Status> {
+        match gate {
+            Gate::Open | Gate::Replay(None) | Gate::Ready(_) => {}
+            Gate::Replay(Some(index)) => {
+                let key = (
+                    session.map(str::to_string),
+                    version.filter(|v| !v.is_empty()).map(str::to_string),
+                    track.clone(),
+                );
+                self.cursors.insert(key, *index);
+            }
+            Gate::Error(status) => return Err(status.clone()),
+            Gate::Closed => return Ok(None),
+        }
+        Ok(self.next_response(session, version, track))
+    }
+
+    /// The recorded delay before the `model_stream_infer` response at `index`.
+    fn stream_delay(&self, version: Option<&str>, index: usize) -> u64 {
+        self.stream(version)
+            .and_then(|stream| stream.model_stream_infer_delays.get(index))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn stream_mut(&mut self, version: Option<&str>) -> &mut RecordedStream {
+        match version {
+            Some(version) if !version.is_empty() => {
+                self.versions.entry(version.to_string()).or_default()
+            }
+            _ => &mut self.unversioned,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Default, Debug)]
+struct RecordedStreams {
+    model_map: BTreeMap<String, RecordedModel>,
+    /// Whether anything was recorded since the recording was loaded or last
+    /// saved.
+    #[serde(skip)]
+    dirty: bool,
+    /// Whether this was read from a recording, or is the target of recording,
+    /// rather than left empty by having nothing loaded.
+    #[serde(skip)]
+    loaded: bool,
+}
+
+impl RecordedStreams {
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let fin = std::fs::File::open(path)
+            .map_err(|e| format!("failed to open recording '{}': {}", path, e))?;
+        let fin_gz = flate2::read::GzDecoder::new(fin);
+        let recording: Self = serde_json::from_reader(fin_gz)
+            .map_err(|e| format!("failed to read recording '{}': {}", path, e))?;
+        Ok(RecordedStreams {
+            loaded: true,
+            ..recording
+        })
+    }
+
+    /// Reads a recording held in memory, gzip-compressed as on disk or plain
+    /// JSON.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let recording = if bytes.starts_with(&[0x1f, 0x8b]) {
+            serde_json::from_reader(flate2::read::GzDecoder::new(bytes))
+        } else {
+            serde_json::from_slice(bytes)
+        };
+        let recording: Self = recording.map_err(|e| format!("failed to read recording: {}", e))?;
+        Ok(RecordedStreams {
+            loaded: true,
+            ..recording
+        })
+    }
+
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let fout = std::fs::File::create(path)
+            .map_err(|e| format!("failed to create recording '{}': {}", path, e))?;
+        let mut fout_gz = flate2::write::GzEncoder::new(fout, flate2::Compression::default());
+        serde_json::to_writer(&mut fout_gz, self)?;
+        fout_gz.finish()?;
+        Ok(())
+    }
+
+    /// Starts replaying every model, or just `model`, from the beginning, in
+    /// every session or just `session`, which is forgotten.
+    fn reset_cursors(&mut self, model: Option<&str>, session: Option<&str>) {
+        for (name, recorded) in self.model_map.iter_mut() {
+            if model.is_none_or(|model| model == name.as_str()) {
+                recorded.cursors.retain(|(s, _, _), _| {
+                    session.is_some_and(|session| s.as_deref() != Some(session))
+                });
+            }
+        }
+    }
+
+    /// The model to record responses of `name` into, marking the recording
+    /// as needing a save.
+    fn record_into(&mut self, name: &str) -> &mut RecordedModel {
+        self.dirty = true;
+        self.model_map.entry(name.to_string()).or_default()
+    }
+
+    /// The version label metrics of a request for `name` are kept under: the
+    /// version `requested` resolves to, empty for unversioned or unrecorded
+    /// models, and none when it does not resolve, so requests cannot add label
+    /// values of their own.
+    fn metrics_version(&self, name: &str, requested: &str) -> Option<String> {
+        match self.model_map.get(name) {
+            Some(model) => model
+                .resolve_version(name, requested)
+                .ok()
+                .map(Option::unwrap_or_default),
+            None => Some(String::new()),
+        }
+    }
+
+    /// The sessions that have replayed anything since they were last reset.
+    fn sessions(&self) -> BTreeSet<&str> {
+        self.model_map
+            .values()
+            .flat_map(|recorded| recorded.cursors.keys())
+            .filter_map(|(session, _, _)| session.as_deref())
+            .collect()
+    }
+}
+
+type ResponseStream =
+    Pin<Box<dyn Stream<Item = Result<server::ModelStreamInferResponse, Status>> + Send>>;
+
+#[derive(Debug, Default)]
+struct MockInferenceService {
+    cassettes: Arc<Cassettes>,
+    shared_memory: Arc<Mutex<SharedMemoryRegions>>,
+    trace: Arc<Mutex<TraceManager>>,
+    metrics: Arc<Mutex<Metrics>>,
+    upstream: Arc<Mutex<Upstream>>,
+    stubs: Arc<Mutex<Stubs>>,
+    scenarios: Arc<Mutex<Scenarios>>,
+    journal: Arc<Mutex<Journal>>,
+    /// The metadata header naming the session whose replay cursors a request
+    /// advances.
+    session_header: String,
+    /// How responses are fabricated for models without recorded ones.
+    synthetic: Option<Fill>,
+}
+
+type DecoupledResponses =
+    tokio::sync::mpsc::Receiver<Result<server::ModelStreamInferResponse, Status>>;
+
+/// Request parameter asking Triton to mark the last response of a decoupled
+/// request, and the response parameter carrying that mark.
+const ENABLE_EMPTY_FINAL_RESPONSE: &str = "triton_enable_empty_final_response";
+const FINAL_RESPONSE: &str = "triton_final_response";
+
+fn final_response_flag(resp: &server::ModelStreamInferResponse) -> Option<bool> {
+    use server::infer_parameter::ParameterChoice;
+
+    let param = resp
+        .infer_response
+        .as_ref()?
+        .parameters
+        .get(FINAL_RESPONSE)?;
+    match param.parameter_choice {
+        Some(ParameterChoice::BoolParam(v)) => Some(v),
+        _ => None,
+    }
+}
+
+/// Whether a response is the empty marker that only closes a decoupled
+/// request, which is not forwarded to clients that did not ask for it.
+fn is_empty_final_response(resp: &server::ModelStreamInferResponse) -> bool {
+    final_response_flag(resp) == Some(true)
+        && resp
+            .infer_response
+            .as_ref()
+            .map(|resp| resp.outputs.is_empty())
+            .unwrap_or(true)
+}
+
+fn stream_response_result(
+    resp: server::ModelStreamInferResponse,
+) -> Result<server::ModelStreamInferResponse, Status> {
+    if resp.error_message.is_empty() {
+        Ok(resp)
+    } else {
+        Err(Status::internal(resp.error_message))
+    }
+}
+
+impl MockInferenceService {
+    #[allow(clippy::too_many_arguments)]
+    fn new_with(
+        cassettes: Arc<Cassettes>,
+        shared_memory: Arc<Mutex<SharedMemoryRegions>>,
+        trace: Arc<Mutex<TraceManager>>,
+        metrics: Arc<Mutex<Metrics>>,
+        upstream: Arc<Mutex<Upstream>>,
+        stubs: Arc<Mutex<Stubs>>,
+        scenarios: Arc<Mutex<Scenarios>>,
+        journal: Arc<Mutex<Journal>>,
+        session_header: String,
+        synthetic: Option<Fill>,
+    ) -> Self {
+        MockInferenceService {
+            cassettes,
+            shared_memory,
+            trace,
+            metrics,
+            upstream,
+            stubs,
+            scenarios,
+            journal,
+            session_header,
+            synthetic,
+        }
+    }
+
+    /// The session a request belongs to, if it carries the session header.
+    fn session(&self, caller: &Caller) -> Option<String> {
+        caller.headers.get(&self.session_header).cloned()
+    }
+
+    /// The recorded config of a model in the cassette the caller chose, for
+    /// frontends that need tensor datatypes without consuming a recorded
+    /// `model_config` response.
+    async fn recorded_config(
+        &self,
+        caller: &Caller,
+        name: &str,
+        version: &str,
+    ) -> Option<server::ModelConfig> {
+        let recorded_streams = self.cassettes.select(caller).await.ok()?;
+        let recorded_streams = recorded_streams.lock().await;
+        let model = recorded_streams.model_map.get(name)?;
+        let version = model.resolve_version(name, version).ok()?;
+        model.config(version.as_deref())
+    }
+
+    /// Answers a `model_metadata` request from upstream or from the recorded
+    /// model config, noting which in `outcome`.
+    async fn metadata(
+        &self,
+        request: server::ModelMetadataRequest,
+        caller: &Caller,
+        outcome: &mut Outcome,
+    ) -> Result<server::ModelMetadataResponse, Status> {
+        let name = request.name.clone();
+        if !self.cassettes.knows(&name).await {
+            log::error!(
+                "model_metadata: unknown model '{}', request: {:?}",
+                name,
+                request
+            );
+            return Err(tonic::Status::not_found(format!(
+                "model_metadata: model not found: {}",
+                name
+            )));
+        }
+        let recorded_streams = self.cassettes.select(caller).await?;
+        let upstream = self.upstream.lock().await.route(&name)?;
+        if let Some((mut client, _)) = upstream {
+            *outcome = Outcome::Upstream;
+            return client
+                .model_metadata(tonic::Request::new(request))
+                .await
+                .map(tonic::Response::into_inner)
+                .map_err(|e| {
+                    log::error!("model_metadata: error: {:?}", e);
+                    e
+                });
+        }
+        let recorded_streams = recorded_streams.lock().await;
+        let model = recorded_streams.model_map.get(&name).ok_or_else(|| {
+            tonic::Status::unavailable("model_metadata: no recorded model config")
+        })?;
+        let version = model.resolve_version(&name, &request.version)?;
+        let config = model.config(version.as_deref()).ok_or_else(|| {
+            tonic::Status::unavailable("model_metadata: no recorded model config")
+        })?;
+        let batch_dim = if config.max_batch_size > 0 {
+            vec![-1]
+        } else {
+            vec![]
+        };
+        let tensor = |name: &str, data_type: i32, dims: &[i64]| {
+            server::model_metadata_response::TensorMetadata {
+                name: name.to_string(),
+                datatype: tensor::config_datatype(data_type),
+                shape: batch_dim.iter().chain(dims).copied().collect(),
+            }
+        };
+        *outcome = Outcome::Config;
+        Ok(server::ModelMetadataResponse {
+            name: name.clone(),
+            versions: model
+                .loaded_versions()
+                .iter()
+                .map(|v| v.to_string())
+                .collect(),
+            platform: if config.platform.is_empty() {
+                config.backend.clone()
+            } else {
+                config.platform.clone()
+            },
+            inputs: config
+                .input
+                .iter()
+                .map(|input| tensor(&input.name, input.data_type, &input.dims))
+                .collect(),
+            outputs: config
+                .output
+                .iter()
+                .map(|output| tensor(&output.name, output.data_type, &output.dims))
+                .collect(),
+        })
+    }
+
+    /// Runs a single request against a decoupled model and streams back all of
+    /// its responses, ending after the first error. Recording asks upstream
+    /// for an empty final response so the end of each request is known on
+    /// replay; replay reproduces the recorded delays between responses.
+    async fn decoupled_infer(
+        &self,
+        request: tonic::Request<server::ModelInferRequest>,
+    ) -> Result<DecoupledResponses, Status> {
+        let caller = Caller::from_request(&request);
+        let request = request.into_inner();
+        let entry = JournalEntry::new(
+            "model_stream_infer",
+            &request.model_name,
+            &request.model_version,
+            caller.clone(),
+            journal::infer_request_json(&request),
+        );
+        let mut outcome = Outcome::Miss;
+        let result = self
+            .decoupled_responses(request, &caller, &mut outcome)
+            .await;
+        self.journal
+            .lock()
+            .await
+            .record(entry.finished(outcome, result.as_ref().err()));
+        result
+    }
+
+    async fn decoupled_responses(
+        &self,
+        mut request: server::ModelInferRequest,
+        caller: &Caller,
+        outcome: &mut Outcome,
+    ) -> Result<DecoupledResponses, Status> {
+        let name = request.model_name.clone();
+        log::info!("decoupled_infer: '{}'", name);
+        if !self.cassettes.knows(&name).await {
+            return Err(Status::not_found(format!(
+                "decoupled_infer: model not found: {}",
+                name
+            )));
+        }
+        shared_memory::reject_refs(&request)?;
+        let stubbed = self.stubs.lock().await.infer(Rpc::StreamInfer, &request);
+        if let Some((id, delay, result)) = stubbed {
+            *outcome = Outcome::Stub { id };
+            let (tx, rx) = tokio::sync::mpsc::channel(1);
+            tokio::spawn(async move {
+                tokio::time::sleep(delay).await;
+                let _ = tx
+                    .send(stream_response_result(stubs::stream_response(result)))
+                    .await;
+            });
+            return Ok(rx);
+        }
+        let started = std::time::Instant::now();
+        let name_label = name.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        let session = self.session(caller);
+        let recorded_streams = self.cassettes.select(caller).await?;
+        let metrics_version = recorded_streams
+            .lock()
+            .await
+            .metrics_version(&name, &request.model_version);
+        if let Some(version) = &metrics_version {
+            self.metrics.lock().await.request_started(&name, version);
+        }
+        let metrics = self.metrics.clone();
+        let finished = move |failed: bool| async move {
+            let Some(version) = metrics_version else {
+                return;
+            };
+            let mut metrics = metrics.lock().await;
+            metrics.request_finished(&name_label, &version);
+            if failed {
+                metrics.failure(&name_label, &version, started.elapsed());
+            } else {
+                let timings = InferTimings {
+                    compute_infer: started.elapsed(),
+                    ..Default::default()
+                };
+                metrics.success(&name_label, &version, 1, started.elapsed(), &timings);
+            }
+        };
+        let on_error = finished.clone();
+        let result = async {
+            let upstream = self.upstream.lock().await.route(&name)?;
+            if let Some((mut client, record)) = upstream {
+                *outcome = Outcome::Upstream;
+                request.parameters.insert(
+                    ENABLE_EMPTY_FINAL_RESPONSE.to_string(),
+                    server::InferParameter {
+                        parameter_choice: Some(
+                            server::infer_parameter::ParameterChoice::BoolParam(true),
+                        ),
+                    },
+                );
+                let requested_version = request.model_version.clone();
+                let mut last_event = std::time::Instant::now();
+                let mut resp_stream = client
+                    .model_stream_infer(tokio_stream::once(request))
+                    .await?
+                    .into_inner();
+                tokio::spawn(async move {
+                    let mut failed = false;
+                    loop {
+                        let resp = match resp_stream.message().await {
+                            Ok(Some(resp)) => resp,
+                            Ok(None) => break,
+                            Err(status) => {
+                                log::error!("decoupled_infer: error: {:?}", status);
+                                let _ = tx.send(Err(status)).await;
+                                failed = true;
+                                break;
+                            }
+                        };
+                        let delay = last_event.elapsed().as_micros() as u64;
+                        last_event = std::time::Instant::now();
+                        if record {
+                            let mut recorded_streams = recorded_streams.lock().await;
+                            let version = match resp.infer_response.as_ref() {
+                                Some(resp) if !resp.model_version.is_empty() => &resp.model_version,
+                                _ => &requested_version,
+                            };
+                            let stream = recorded_streams
+                                .record_into(&name)
+                                .stream_mut(Some(version));
+                            stream
+                                .model_stream_infer
+                                .push_back(serde_json::to_string(&resp).unwrap());
+                            stream.model_stream_infer_delays.push_back(delay);
+                        }
+                        if is_empty_final_response(&resp) {
+                            continue;
+                        }
+                        let resp = stream_response_result(resp);
+                        failed = resp.is_err();
+                        if tx.send(resp).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                    finished(failed).await;
+                });
+            } else {
+                let mut responses = Vec::new();
+                let (gate, transition) = self.scenarios.lock().await.gate(
+                    Rpc::StreamInfer,
+                    &name,
+                    &request.model_version,
+                );
+                let version = {
+                    let mut recorded_streams = recorded_streams.lock().await;
+                    let model = recorded_streams.model_map.get_mut(&name).ok_or_else(|| {
+                        Status::unavailable("decoupled_infer: no recorded response")
+                    })?;
+                    let version = model.resolve_version(&name, &request.model_version)?;
+                    let mut next = model.gated_response(
+                        &gate,
+                        session.as_deref(),
+                        version.as_deref(),
+                        Track::StreamInfer,
+                    )?;
+                    while let Some((index, json)) = next {
+                        let resp: server::ModelStreamInferResponse =
+                            serde_json::from_str(&json).unwrap();
+                        let delay = model.stream_delay(version.as_deref(), index);
+                        if responses.is_empty() {
+                            *outcome = Outcome::Recording { index };
+                        }
+                        // Recordings without final response marks hold one
+                        // response per request.
+                        let last = final_response_flag(&resp).unwrap_or(true);
+                        responses.push((resp, delay));
+                        if last {
+                            break;
+                        }
+                        next = model.next_response(
+                            session.as_deref(),
+                            version.as_deref(),
+                            Track::StreamInfer,
+                        );
+                    }
+                    version.unwrap_or_default()
+                };
+                self.metrics.lock().await.replay(
+                    &name,
+                    &version,
+                    "model_stream_infer",
+                    !responses.is_empty(),
+                );
+                if responses.is_empty() {
+                    return Err(Status::unavailable("decoupled_infer: no recorded response"));
+                }
+                self.scenarios.lock().await.advance(transition);
+                tokio::spawn(async move {
+                    let mut failed = false;
+                    for (resp, delay) in responses {
+                        tokio::time::sleep(std::time::Duration::from_micros(delay)).await;
+                        if is_empty_final_response(&resp) {
+                            continue;
+                        }
+                        let resp = stream_response_result(resp);
+                        failed = resp.is_err();
+                        if tx.send(resp).await.is_err() || failed {
+                            break;
+                        }
+                    }
+                    finished(failed).await;
+                });
+            }
+            Ok(rx)
+        }
+        .await;
+        if result.is_err() {
+            on_error(true).await;
+        }
+        result
+    }
+}
+
+const CLIENT_PORTS: &[(&[&str], &str)] = &[
+    /*
+        (
+            &[
+                "acronym_detector",
+                "document_classifier",
+                "sentence_embed",
+                "ner",
+            ],
+            "8302",
+        ),
+        (&["ingestor"], "8303"),
+    (&["cross_encoder", "coreference_resolution"], "8304"),
+    */
+    (&["llama_7b"], "8305"),
+    /*
+        (&["keybert", "ingestor_vllm"], "8306"),
+    */
+    (&["mistral_7b_instruct"], "8307"),
+];
+
+//const SERVER_PORTS: &[&str] = &["8002", "8003", "8004", "8005", "8006", "8007"];
+const SERVER_PORTS: &[&str] = &["8005", "8007"];
+
+const MODELS: &[&str] = &[
+    "acronym_detector",
+    "document_classifier",
+    "sentence_embed",
+    "ner",
+    "keybert",
+    "ingestor",
+    "coreference_resolution",
+    "cross_encoder",
+    "llama_7b",
+    "mistral_7b_instruct",
+];
+
+/// Triton reports ready once its models are loaded and until shutdown
+/// begins; the mock's models are loaded with its recording.
+async fn server_is_ready(cassettes: &Cassettes) -> bool {
+    !cassettes.is_closed() && cassettes.default_cassette().lock().await.loaded
+}
+
+fn model_is_ready(recorded_streams: &RecordedStreams, name: &str, version: &str) -> bool {
+    match recorded_streams.model_map.get(name) {
+        Some(model) => model.is_ready(version),
+        None => MODELS.contains(&name) && version.is_empty(),
+    }
+}
+
+#[tonic::async_trait]
+impl GrpcInferenceService for MockInferenceService {
+    type ModelStreamInferStream = ResponseStream;
+
+    async fn server_live(
+        &self,
+        _request: tonic::Request<server::ServerLiveRequest>,
+    ) -> std::result::Result<tonic::Response<server::ServerLiveResponse>, tonic::Status> {
+        Ok(tonic::Response::new(server::ServerLiveResponse {
+            live: true,
+        }))
+    }
+
+    async fn model_ready(
+        &self,
+        request: tonic::Request<server::ModelReadyRequest>,
+    ) -> std::result::Result<tonic::Response<server::ModelReadyResponse>, tonic::Status> {
+        let name = &request.get_ref().name;
+        let version = &request.get_ref().version;
+        let caller = Caller::from_request(&request);
+        let mut outcome = Outcome::Miss;
+        let result = async {
+            let recorded_streams = self.cassettes.select(&caller).await?;
+            let (gate, transition) = self.scenarios.lock().await.gate(Rpc::Ready, name, version);
+            let ready = match gate {
+                Gate::Error(status) => return Err(status),
+                Gate::Ready(ready) => ready,
+                // Readiness has no recorded response to withhold.
+                Gate::Closed | Gate::Open | Gate::Replay(_) => {
+                    model_is_ready(&*recorded_streams.lock().await, name, version)
+                }
+            };
+            self.scenarios.lock().await.advance(transition);
+            outcome = Outcome::Config;
+            Ok(ready)
+        }
+        .await;
+        let entry = JournalEntry::new(
+            "model_ready",
+            name,
+            version,
+            caller,
+            serde_json::json!({ "name": name, "version": version }),
+        );
+        self.journal
+            .lock()
+            .await
+            .record(entry.finished(outcome, result.as_ref().err()));
+        let ready = result?;
+        if ready {
+            log::info!("model_ready: {:?}", request);
+        } else {
+            log::error!(
+                "model_ready: unknown model '{}' version '{}', request: {:?}",
+                name,
+                version,
+                request
+            );
+        }
+        Ok(tonic::Response::new(server::ModelReadyResponse { ready }))
+    }
+
+    async fn server_ready(
+        &self,
+        _request: tonic::Request<server::ServerReadyRequest>,
+    ) -> std::result::Result<tonic::Response<server::ServerReadyResponse>, tonic::Status> {
+        Ok(tonic::Response::new(server::ServerReadyResponse {
+            ready: server_is_ready(&self.cassettes).await,
+        }))
+    }
+
+    async fn model_infer(
+        &self,
+        request: tonic::Request<server::ModelInferRequest>,
+    ) -> std::result::Result<tonic::Response<server::ModelInferResponse>, tonic::Status> {
+        let name = request.get_ref().model_name.to_string();
+        log::info!("model_infer: '{}'", name);
+        let caller = Caller::from_request(&request);
+        let session = self.session(&caller);
+        let requested_version = request.get_ref().model_version.clone();
+        if !self.cassettes.knows(&name).await {
+            log::error!(
+                "model_infer: unknown model '{}', request: {:?}",
+                name,
+                request
+            );
+            let status =
+                tonic::Status::not_found(format!("model_infer: model not found: {}", name));
+            let entry = JournalEntry::new(
+                "model_infer",
+                &name,
+                &requested_version,
+                caller,
+                journal::infer_request_json(request.get_ref()),
+            );
+            self.journal
+                .lock()
+                .await
+                .record(entry.finished(Outcome::Miss, Some(&status)));
+            return Err(status);
+        }
+        let started = std::time::Instant::now();
+        let metrics_version = match self.cassettes.select(&caller).await {
+            Ok(recorded_streams) => recorded_streams
+                .lock()
+                .await
+                .metrics_version(&name, &requested_version),
+            Err(_) => None,
+        };
+        if let Some(version) = &metrics_version {
+            self.metrics.lock().await.request_started(&name, version);
+        }
+        let mut timings = InferTimings::default();
+        let mut batch_size = 1;
+        let mut outcome = Outcome::Miss;
+        let mut journal_request = serde_json::Value::Null;
+        let result = async {
+            let mut request = request.into_inner();
+            let shm_outputs = {
+                let shared_memory = self.shared_memory.lock().await;
+                shared_memory.resolve_inputs(&mut request)?;
+                shared_memory::take_output_refs(&mut request.outputs)?
+            };
+            journal_request = journal::infer_request_json(&request);
+            timings.compute_input = started.elapsed();
+            let mut trace = self.trace.lock().await.start(&name, &request.model_version);
+            if let Some(trace) = trace.as_mut() {
+                trace.inputs(&request);
+                trace.stamp("QUEUE_START");
+                trace.stamp("COMPUTE_START");
+            }
+            let batch_dim = request
+                .inputs
+                .first()
+                .and_then(|input| input.shape.first())
+                .map(|dim| (*dim).max(1) as u64);
+            let stubbed = self.stubs.lock().await.infer(Rpc::Infer, &request);
+            let mut resp = match stubbed {
+                Some((id, delay, result)) => {
+                    outcome = Outcome::Stub { id };
+                    timings.queue = started.elapsed() - timings.compute_input;
+                    tokio::time::sleep(delay).await;
+                    timings.compute_infer = delay;
+                    result?
+                }
+                None => {
+                    let recorded_streams = self.cassettes.select(&caller).await?;
+                    let mut recorded_stream = recorded_streams.lock().await;
+                    timings.queue = started.elapsed() - timings.compute_input;
+                    let compute_start = std::time::Instant::now();
+                    if let Some(model) = recorded_stream.model_map.get(&name) {
+                        let version = model.resolve_version(&name, &requested_version).ok();
+                        if model
+                            .config(version.flatten().as_deref())
+                            .is_some_and(|config| config.max_batch_size > 0)
+                        {
+                            batch_size = batch_dim.unwrap_or(1);
+                        }
+                    }
+                    let upstream = self.upstream.lock().await.route(&name)?;
+                    let resp = if let Some((mut client, record)) = upstream {
+                        outcome = Outcome::Upstream;
+                        let resp = client
+                            .model_infer(tonic::Request::new(request))
+                            .await
+                            .map(|v| {
+                                let v = v.into_inner();
+                                let version = if v.model_version.is_empty() {
+                                    &requested_version
+                                } else {
+                                    &v.model_version
+                                };
+                                if record {
+                                    let model_infer = &mut recorded_stream
+                                        .record_into(&name)
+                                        .stream_mut(Some(version))
+                                        .model_infer;
+                                    model_infer.push_back(serde_json::to_string(&v).unwrap());
+                                }
+                                v
+                            })
+                            .map_err(|e| {
+                                log::error!("model_infer: error: {:?}", e);
+                                e
+                            })?;
+                        log::debug!("model_infer: resp: {resp:?}");
+                        resp
+                    } else {
+                        let model = recorded_stream.model_map.get_mut(&name).ok_or_else(|| {
+                            tonic::Status::unavailable("model_infer: no recorded response")
+                        })?;
+                        let version = model.resolve_version(&name, &requested_version)?;
+                        let (gate, transition) =
+                            self.scenarios
+                                .lock()
+                                .await
+                                .gate(Rpc::Infer, &name, &requested_version);
+                        let json_resp = model
+                            .gated_response(
+                                &gate,
+                                session.as_deref(),
+                                version.as_deref(),
+                                Track::Infer,
+                            )?
+                            .map(|(index, json)| {
+                                outcome = Outcome::Recording { index };
+                                json
+                            });
+                        let synthetic = self
+                            .synthetic
+                            .filter(|_| json_resp.is_none() && !matches!(gate, Gate::Closed))
+                            .filter(|_| {
+                                model
+                                    .stream(version.as_deref())
+                                    .is_none_or(|stream| stream.model_infer.is_empty())
+                            })
+                            .and_then(|fill| Some((fill, model.config(version.as_deref())?)));
+                        // Synthesized responses are neither hits nor misses.
+                        if json_resp.is_some() || synthetic.is_none() {
+                            self.metrics.lock().await.replay(
+                                &name,
+                                version.as_deref().unwrap_or_default(),
+                                "model_infer",
+                                json_resp.is_some(),
+                            );
+                        }
+                        if let Some(json_resp) = json_resp {
+                            self.scenarios.lock().await.advance(transition);
+                            serde_json::from_str(&json_resp).unwrap()
+                        } else if let Some((fill, config)) = synthetic {
+                            outcome = Outcome::Synthetic;
+                            let resp = synthetic::response(
+                                &config,
+                                &request,
+                                version.as_deref().unwrap_or(&requested_version),
+                                fill,
+                            )?;
+                            self.scenarios.lock().await.advance(transition);
+                            resp
+                        } else {
+                            return Err(tonic::Status::unavailable(
+                                "model_infer: no recorded response",
+                            ));
+                        }
+                    };
+                    drop(recorded_stream);
+                    timings.compute_infer = compute_start.elapsed();
+                    resp
+                }
+            };
+            if let Some(trace) = trace.as_mut() {
+                trace.stamp("COMPUTE_END");
+                trace.outputs(&resp);
+            }
+            let output_start = std::time::Instant::now();
+            if !shm_outputs.is_empty() {
+                self.shared_memory
+                    .lock()
+                    .await
+                    .write_outputs(&shm_outputs, &mut resp)?;
+            }
+            timings.compute_output = output_start.elapsed();
+            if let Some(mut trace) = trace {
+                trace.stamp("GRPC_SEND_START");
+                trace.stamp("GRPC_SEND_END");
+                self.trace.lock().await.finish(trace);
+            }
+            Ok(resp)
+        }
+        .await;
+        let mut metrics = self.metrics.lock().await;
+        if let Some(version) = &metrics_version {
+            metrics.request_finished(&name, version);
+        }
+        match &result {
+            Ok(resp) if !resp.model_version.is_empty() => metrics.success(
+                &name,
+                &resp.model_version,
+                batch_size,
+                started.elapsed(),
+                &timings,
+            ),
+            Ok(_) => {
+                if let Some(version) = &metrics_version {
+                    metrics.success(&name, version, batch_size, started.elapsed(), &timings)
+                }
+            }
+            Err(_) => {
+                if let Some(version) = &metrics_version {
+                    metrics.failure(&name, version, started.elapsed())
+                }
+            }
+        }
+        drop(metrics);
+        let entry = JournalEntry::new(
+            "model_infer",
+            &name,
+            &requested_version,
+            caller,
+            journal_request,
+        );
+        self.journal
+            .lock()
+            .await
+            .record(entry.finished(outcome, result.as_ref().err()));
+        result.map(tonic::Response::new)
+    }
+
+    async fn model_config(
+        &self,
+        request: tonic::Request<server::ModelConfigRequest>,
+    ) -> std::result::Result<tonic::Response<server::ModelConfigResponse>, tonic::Status> {
+        let name = request.get_ref().name.to_string();
+        log::info!("model_config: '{}'", name);
+        let caller = Caller::from_request(&request);
+        let session = self.session(&caller);
+        let requested_version = request.get_ref().version.clone();
+        let journal_request = serde_json::json!({ "name": name, "version": requested_version });
+        let mut outcome = Outcome::Miss;
+        let result = async {
+            if !self.cassettes.knows(&name).await {
+                log::error!(
+                    "model_config: unknown model '{}', request: {:?}",
+                    name,
+                    request
+                );
+                return Err(tonic::Status::not_found(format!(
+                    "model_config: model not found: {}",
+                    name
+                )));
+            }
+            let request = request.into_inner();
+            let stubbed = self.stubs.lock().await.config(&request);
+            if let Some((id, delay, result)) = stubbed {
+                outcome = Outcome::Stub { id };
+                tokio::time::sleep(delay).await;
+                return result;
+            }
+            let json = serde_json::to_string(&request).unwrap();
+            let recorded_streams = self.cassettes.select(&caller).await?;
+            let mut recorded_stream = recorded_streams.lock().await;
+            let upstream = self.upstream.lock().await.route(&name)?;
+            if let Some((mut client, record)) = upstream {
+                outcome = Outcome::Upstream;
+                let resp = client
+                    .model_config(tonic::Request::new(request))
+                    .await
+                    .map(|v| {
+                        let v = v.into_inner();
+                        if record {
+                            let model_config = &mut recorded_stream
+                                .record_into(&name)
+                                .stream_mut(Some(&requested_version))
+                                .model_config;
+                            let outputs = model_config.entry(json).or_insert(VecDeque::new());
+                            outputs.push_back(serde_json::to_string(&v).unwrap());
+                        }
+                        v
+                    })
+                    .map_err(|e| {
+                        log::error!("model_config: error: {:?}", e);
+                        e
+                    })?;
+                log::debug!("model_config: resp: {resp:?}");
+                Ok(resp)
+            } else {
+                let model = recorded_stream.model_map.get_mut(&name).ok_or_else(|| {
+                    tonic::Status::unavailable("model_config: no recorded response")
+                })?;
+                // A config requested without a version may have been recorded that
+                // way; otherwise it is looked up under the latest loaded version.
+                let (gate, transition) =
+                    self.scenarios
+                        .lock()
+                        .await
+                        .gate(Rpc::Config, &name, &requested_version);
+                let mut resp_json =
+                    model.gated_response(&gate, session.as_deref(), None, Track::Config(json))?;
+                if resp_json.is_none() {
+                    if let Some(version) = model.resolve_version(&name, &requested_version)? {
+                        let key = serde_json::to_string(&server::ModelConfigRequest {
+                            version: version.clone(),
+                            ..request
+                        })
+                        .unwrap();
+                        resp_json = model.gated_response(
+                            &gate,
+                            session.as_deref(),
+                            Some(&version),
+                            Track::Config(key),
+                        )?;
+                    }
+                }
+                // Labeled like model_infer, by the version the request
+                // resolves to; requests for unknown versions are left out.
+                let metrics_version = model
+                    .resolve_version(&name, &requested_version)
+                    .ok()
+                    .map(Option::unwrap_or_default);
+                if let Some(version) = metrics_version {
+                    self.metrics.lock().await.replay(
+                        &name,
+                        &version,
+                        "model_config",
+                        resp_json.is_some(),
+                    );
+                }
+                if let Some((index, resp_json)) = resp_json {
+                    outcome = Outcome::Recording { index };
+                    self.scenarios.lock().await.advance(transition);
+                    Ok(serde_json::from_str(&resp_json).unwrap())
+                } else {
+                    Err(tonic::Status::unavailable(
+                        "model_config: no recorded response",
+                    ))
+                }
+            }
+        }
+        .await;
+        let entry = JournalEntry::new(
+            "model_config",
+            &name,
+            &requested_version,
+            caller,
+            journal_request,
+        );
+        self.journal
+            .lock()
+            .await
+            .record(entry.finished(outcome, result.as_ref().err()));
+        result.map(tonic::Response::new)
+    }
+
+    async fn model_stream_infer(
+        &self,
+        request: tonic::Request<tonic::Streaming<server::ModelInferRequest>>,
+    ) -> std::result::Result<tonic::Response<Self::ModelStreamInferStream>, tonic::Status> {
+        use tokio_stream::StreamExt;
+
+        let caller = Caller::from_request(&request);
+        let session = self.session(&caller);
+        let mut stream = request.into_inner();
+        // The first request names the model, which decides whether the stream
+        // is replayed or forwarded upstream.
+        let Some(first) = stream.message().await? else {
+            return Ok(tonic::Response::new(Box::pin(tokio_stream::empty())));
+        };
+        let model_name = first.model_name.clone();
+        let upstream = self.upstream.lock().await.route(&model_name)?;
+        let recorded_streams = self.cassettes.select(&caller).await?;
+        let mut requests = Box::pin(tokio_stream::once(Ok(first)).chain(stream));
+        let (tx2, rx2) = tokio::sync::mpsc::channel(4);
+        let trace = self.trace.clone();
+        let metrics = self.metrics.clone();
+        let stubs = self.stubs.clone();
+        let scenarios = self.scenarios.clone();
+        let journal = self.journal.clone();
+        // Journals a request on the stream with where its response came from.
+        let journal_entry = move |request: &server::ModelInferRequest| {
+            JournalEntry::new(
+                "model_stream_infer",
+                &request.model_name,
+                &request.model_version,
+                caller.clone(),
+                journal::infer_request_json(request),
+            )
+        };
+        if let Some((mut client, record)) = upstream {
+            let (tx, rx) = tokio::sync::mpsc::channel(4);
+            let pending_traces = Arc::new(Mutex::new(VecDeque::new()));
+            let last_event = Arc::new(std::sync::Mutex::new(std::time::Instant::now()));
+            {
+                let recorded_streams = recorded_streams.clone();
+                let trace = trace.clone();
+                let metrics = metrics.clone();
+                let journal = journal.clone();
+                let pending_traces = pending_traces.clone();
+                let last_event = last_event.clone();
+                let stub_tx = tx2.clone();
+                tokio::spawn(async move {
+                    while let Some(Ok(model_infer_request)) = requests.next().await {
+                        if let Err(status) = shared_memory::reject_refs(&model_infer_request) {
+                            journal.lock().await.record(
+                                journal_entry(&model_infer_request)
+                                    .finished(Outcome::Miss, Some(&status)),
+                            );
+                            if stub_tx.send(Err(status)).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        let stubbed = stubs
+                            .lock()
+                            .await
+                            .infer(Rpc::StreamInfer, &model_infer_request);
+                        let entry = journal_entry(&model_infer_request);
+                        if let Some((id, delay, result)) = stubbed {
+                            journal.lock().await.record(
+                                entry.finished(Outcome::Stub { id }, result.as_ref().err()),
+                            );
+                            tokio::time::sleep(delay).await;
+                            let resp = stubs::stream_response(result);
+                            if stub_tx.send(Ok(resp)).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        let mut request_trace = trace.lock().await.start(
+                            &model_infer_request.model_name,
+                            &model_infer_request.model_version,
+                        );
+                        if let Some(request_trace) = request_trace.as_mut() {
+                            request_trace.inputs(&model_infer_request);
+                            request_trace.stamp("QUEUE_START");
+                            request_trace.stamp("COMPUTE_START");
+                        }
+                        let metrics_version = recorded_streams.lock().await.metrics_version(
+                            &model_infer_request.model_name,
+                            &model_infer_request.model_version,
+                        );
+                        if let Some(version) = &metrics_version {
+                            metrics
+                                .lock()
+                                .await
+                                .request_started(&model_infer_request.model_name, version);
+                        }
+                        // The request is journaled once upstream answers it.
+                        pending_traces.lock().await.push_back((
+                            request_trace,
+                            std::time::Instant::now(),
+                            metrics_version,
+                            Some(entry),
+                        ));
+                        if record {
+                            let mut recorded_streams = recorded_streams.lock().await;
+                            let model_map = recorded_streams
+                                .model_map
+                                .entry(model_infer_request.model_name.clone())
+                                .or_default();
+                            let req_json = serde_json::to_string(&model_infer_request).unwrap();
+                            model_map.model_stream_infer_inputs.push_back(req_json);
+                        }
+                        *last_event.lock().unwrap() = std::time::Instant::now();
+                        if tx.send(model_infer_request).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            let req_stream = tokio_stream::wrappers::ReceiverStream::new(rx);
+            let response = client.model_stream_infer(req_stream).await?;
+            let mut resp_stream = response.into_inner();
+            tokio::spawn(async move {
+                loop {
+                    let model_infer_resp = match resp_stream.message().await {
+                        Ok(Some(resp)) => resp,
+                        Ok(None) => break,
+                        Err(status) => {
+                            log::error!("model_stream_infer: error: {:?}", status);
+                            // Requests upstream never answered failed with the stream.
+                            let mut journal = journal.lock().await;
+                            for (_, _, _, entry) in pending_traces.lock().await.drain(..) {
+                                if let Some(entry) = entry {
+                                    journal
+                                        .record(entry.finished(Outcome::Upstream, Some(&status)));
+                                }
+                            }
+                            drop(journal);
+                            let _ = tx2.send(Err(status)).await;
+                            break;
+                        }
+                    };
+                    let delay = {
+                        let mut last_event = last_event.lock().unwrap();
+                        let delay = last_event.elapsed().as_micros() as u64;
+                        *last_event = std::time::Instant::now();
+                        delay
+                    };
+                    let (mut request_trace, started, metrics_version, entry) = pending_traces
+                        .lock()
+                        .await
+                        .pop_front()
+                        .unwrap_or_else(|| (None, std::time::Instant::now(), None, None));
+                    if let Some(entry) = entry {
+                        let error = (!model_infer_resp.error_message.is_empty())
+                            .then(|| tonic::Status::unknown(&model_infer_resp.error_message));
+                        journal
+                            .lock()
+                            .await
+                            .record(entry.finished(Outcome::Upstream, error.as_ref()));
+                    }
+                    let mut metrics = metrics.lock().await;
+                    if let Some(version) = &metrics_version {
+                        metrics.request_finished(&model_name, version);
+                    }
+                    let version = match model_infer_resp.infer_response.as_ref() {
+                        Some(resp) if !resp.model_version.is_empty() => Some(&resp.model_version),
+                        _ => metrics_version.as_ref(),
+                    };
+                    if let Some(version) = version {
+                        if model_infer_resp.error_message.is_empty() {
+                            let timings = InferTimings {
+                                compute_infer: started.elapsed(),
+                                ..Default::default()
+                            };
+                            metrics.success(&model_name, version, 1, started.elapsed(), &timings);
+                        } else {
+                            metrics.failure(&model_name, version, started.elapsed());
+                        }
+                    }
+                    drop(metrics);
+                    if let Some(request_trace) = request_trace.as_mut() {
+                        request_trace.stamp("COMPUTE_END");
+                        if let Some(resp) = &model_infer_resp.infer_response {
+                            request_trace.outputs(resp);
+                        }
+                        request_trace.stamp("GRPC_SEND_START");
+                    }
+                    if record {
+                        let mut recorded_streams = recorded_streams.lock().await;
+                        let model_map = recorded_streams.record_into(&model_name);
+                        if model_map.model_stream_infer_inputs.pop_front().is_none() {
+                            let status = tonic::Status::unavailable(
+                                "model_stream_infer: no recorded response",
+                            );
+                            if tx2.send(Err(status)).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                        let json = serde_json::to_string(&model_infer_resp).unwrap();
+                        let version = model_infer_resp
+                            .infer_response
+                            .as_ref()
+                            .map(|resp| resp.model_version.as_str());
+                        let stream = model_map.stream_mut(version);
+                        stream.model_stream_infer.push_back(json);
+                        stream.model_stream_infer_delays.push_back(delay);
+                    }
+                    if tx2.send(Ok(model_infer_resp)).await.is_err() {
+                        break;
+                    }
+                    if let Some(mut request_trace) = request_trace {
+                        request_trace.stamp("GRPC_SEND_END");
+                        trace.lock().await.finish(request_trace);
+                    }
+                }
+            });
+        } else {
+            tokio::spawn(async move {
+                while let Some(Ok(model_infer_req)) = requests.next().await {
+                    if let Err(status) = shared_memory::reject_refs(&model_infer_req) {
+                        journal.lock().await.record(
+                            journal_entry(&model_infer_req).finished(Outcome::Miss, Some(&status)),
+                        );
+                        if tx2.send(Err(status)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    let started = std::time::Instant::now();
+                    let requested_version = &model_infer_req.model_version;
+                    let metrics_version = recorded_streams
+                        .lock()
+                        .await
+                        .metrics_version(&model_name, requested_version);
+                    if let Some(version) = &metrics_version {
+                        metrics.lock().await.request_started(&model_name, version);
+                    }
+                    let mut request_trace = trace
+                        .lock()
+                        .await
+                        .start(&model_name, &model_infer_req.model_version);
+                    if let Some(request_trace) = request_trace.as_mut() {
+                        request_trace.inputs(&model_infer_req);
+                        request_trace.stamp("QUEUE_START");
+                        request_trace.stamp("COMPUTE_START");
+                    }
+                    let stubbed = stubs.lock().await.infer(Rpc::StreamInfer, &model_infer_req);
+                    let entry = journal_entry(&model_infer_req);
+                    if let Some((id, delay, result)) = stubbed {
+                        journal
+                            .lock()
+                            .await
+                            .record(entry.finished(Outcome::Stub { id }, result.as_ref().err()));
+                        tokio::time::sleep(delay).await;
+                        let resp = stubs::stream_response(result);
+                        if let Some(version) = &metrics_version {
+                            let mut metrics = metrics.lock().await;
+                            metrics.request_finished(&model_name, version);
+                            if resp.error_message.is_empty() {
+                                let timings = InferTimings {
+                                    compute_infer: started.elapsed(),
+                                    ..Default::default()
+                                };
+                                metrics.success(
+                                    &model_name,
+                                    version,
+                                    1,
+                                    started.elapsed(),
+                                    &timings,
+                                );
+                            } else {
+                                metrics.failure(&model_name, version, started.elapsed());
+                            }
+                        }
+                        if let Some(mut request_trace) = request_trace {
+                            request_trace.stamp("COMPUTE_END");
+                            if let Some(resp) = &resp.infer_response {
+                                request_trace.outputs(resp);
+                            }
+                            trace.lock().await.finish(request_trace);
+                        }
+                        if tx2.send(Ok(resp)).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    let (gate, transition) = scenarios.lock().await.gate(
+                        Rpc::StreamInfer,
+                        &model_name,
+                        requested_version,
+                    );
+                    let mut recorded_streams = recorded_streams.lock().await;
+                    let replayed = match recorded_streams.model_map.get_mut(&model_name) {
+                        Some(model) => model
+                            .resolve_version(&model_name, requested_version)
+                            .and_then(|version| {
+                                let next = model.gated_response(
+                                    &gate,
+                                    session.as_deref(),
+                                    version.as_deref(),
+                                    Track::StreamInfer,
+                                )?;
+                                Ok((version, next))
+                            }),
+                        None => Err(tonic::Status::unavailable(
+                            "model_stream_infer: no recorded response",
+                        )),
+                    };
+                    drop(recorded_streams);
+                    let (version, next) = match replayed {
+                        Ok(resolved) => resolved,
+                        Err(status) => {
+                            if let Some(version) = &metrics_version {
+                                let mut metrics = metrics.lock().await;
+                                metrics.request_finished(&model_name, version);
+                                metrics.failure(&model_name, version, started.elapsed());
+                            }
+                            journal
+                                .lock()
+                                .await
+                                .record(entry.finished(Outcome::Miss, Some(&status)));
+                            if tx2.send(Err(status)).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
+                    if next.is_some() {
+                        scenarios.lock().await.advance(transition);
+                    }
+                    let miss =
+                        tonic::Status::unavailable("model_stream_infer: no recorded response");
+                    let (outcome, error) = match &next {
+                        Some((index, _)) => (Outcome::Recording { index: *index }, None),
+                        None => (Outcome::Miss, Some(&miss)),
+                    };
+                    journal.lock().await.record(entry.finished(outcome, error));
+                    let resp_json = next.map(|(_, json)| json);
+                    {
+                        let mut metrics = metrics.lock().await;
+                        let version = version.as_deref().unwrap_or_default();
+                        metrics.request_finished(&model_name, version);
+                        metrics.replay(
+                            &model_name,
+                            version,
+                            "model_stream_infer",
+                            resp_json.is_some(),
+                        );
+                        if resp_json.is_some() {
+                            let timings = InferTimings {
+                                compute_infer: started.elapsed(),
+                                ..Default::default()
+                            };
+                            metrics.success(&model_name, version, 1, started.elapsed(), &timings);
+                        } else {
+                            metrics.failure(&model_name, version, started.elapsed());
+                        }
+                    }
+                    if let Some(resp_json) = resp_json {
+                        let resp: server::ModelStreamInferResponse =
+                            serde_json::from_str(&resp_json).unwrap();
+                        if let Some(request_trace) = request_trace.as_mut() {
+                            request_trace.stamp("COMPUTE_END");
+                            if let Some(resp) = &resp.infer_response {
+                                request_trace.outputs(resp);
+                            }
+                            request_trace.stamp("GRPC_SEND_START");
+                        }
+                        if tx2.send(Ok(resp)).await.is_err() {
+                            break;
+                        }
+                        if let Some(mut request_trace) = request_trace {
+                            request_trace.stamp("GRPC_SEND_END");
+                            trace.lock().await.finish(request_trace);
+                        }
+                    } else if tx2.send(Err(miss)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Ok(tonic::Response::new(Box::pin(
+            tokio_stream::wrappers::ReceiverStream::new(rx2),
+        )))
+    }
+
+    async fn log_settings(
+        &self,
+        request: tonic::Request<server::LogSettingsRequest>,
+    ) -> std::result::Result<tonic::Response<server::LogSettingsResponse>, tonic::Status> {
+        log::info!("log_settings: {:?}", request);
+        logging::update_settings(request.get_ref()).map(tonic::Response::new)
+    }
+
+    async fn trace_setting(
+        &self,
+        request: tonic::Request<server::TraceSettingRequest>,
+    ) -> std::result::Result<tonic::Response<server::TraceSettingResponse>, tonic::Status> {
+        log::info!("trace_setting: {:?}", request);
+        let request = request.get_ref();
+        let mut trace = self.trace.lock().await;
+        trace
+            .update(&request.model_name, &request.settings)
+            .map(tonic::Response::new)
+    }
+
+    async fn model_metadata(
+        &self,
+        request: tonic::Request<server::ModelMetadataRequest>,
+    ) -> std::result::Result<tonic::Response<server::ModelMetadataResponse>, tonic::Status> {
+        log::info!("model_metadata: '{}'", request.get_ref().name);
+        let caller = Caller::from_request(&request);
+        let request = request.into_inner();
+        let entry = JournalEntry::new(
+            "model_metadata",
+            &request.name,
+            &request.version,
+            caller.clone(),
+            serde_json::json!({ "name": request.name, "version": request.version }),
+        );
+        let mut outcome = Outcome::Miss;
+        let result = self.metadata(request, &caller, &mut outcome).await;
+        self.journal
+            .lock()
+            .await
+            .record(entry.finished(outcome, result.as_ref().err()));
+        result.map(tonic::Response::new)
+    }
+
+    async fn server_metadata(
+        &self,
+        request: tonic::Request<server::ServerMetadataRequest>,
+    ) -> std::result::Result<tonic::Response<server::ServerMetadataResponse>, tonic::Status> {
+        log::warn!("Not implemented: server_metadata: {:?}", request);
+        return Err(tonic::Status::unimplemented(
+            "server_metadata not implemented",
+        ));
+    }
+
+    async fn model_statistics(
+        &self,
+        request: tonic::Request<server::ModelStatisticsRequest>,
+    ) -> std::result::Result<tonic::Response<server::ModelStatisticsResponse>, tonic::Status> {
+        log::warn!("Not implemented: model_statistics: {:?}", request);
+        return Err(tonic::Status::unimplemented(
+            "model_statistics not implemented",
+        ));
+    }
+
+    async fn repository_index(
+        &self,
+        request: tonic::Request<server::RepositoryIndexRequest>,
+    ) -> std::result::Result<tonic::Response<server::RepositoryIndexResponse>, tonic::Status> {
+        log::warn!("Not implemented: repository_index: {:?}", request);
+        return Err(tonic::Status::unimplemented(
+            "repository_index not implemented",
+        ));
+    }
+
+    async fn repository_model_load(
+        &self,
+        request: tonic::Request<server::RepositoryModelLoadRequest>,
+    ) -> std::result::Result<tonic::Response<server::RepositoryModelLoadResponse>, tonic::Status>
+    {
+        log::warn!("Not implemented: repository_model_load: {:?}", request);
+        return Err(tonic::Status::unimplemented(
+            "repository_model_load not implemented",
+        ));
+    }
+
+    async fn repository_model_unload(
+        &self,
+        request: tonic::Request<server::RepositoryModelUnloadRequest>,
+    ) -> std::result::Result<tonic::Response<server::RepositoryModelUnloadResponse>, tonic::Status>
+    {
+        log::warn!("Not implemented: repository_model_unload: {:?}", request);
+        return Err(tonic::Status::unimplemented(
+            "repository_model_unload not implemented",
+        ));
+    }
+
+    async fn cuda_shared_memory_status(
+        &self,
+        request: tonic::Request<server::CudaSharedMemoryStatusRequest>,
+    ) -> std::result::Result<tonic::Response<server::CudaSharedMemoryStatusResponse>, tonic::Status>
+    {
+        log::info!("cuda_shared_memory_status: {:?}", request);
+        let name = &request.get_ref().name;
+        let shared_memory = self.shared_memory.lock().await;
+        shared_memory.cuda_status(name).map(tonic::Response::new)
+    }
+
+    async fn system_shared_memory_status(
+        &self,
+        request: tonic::Request<server::SystemSharedMemoryStatusRequest>,
+    ) -> std::result::Result<tonic::Response<server::SystemSharedMemoryStatusResponse>, tonic::Status>
+    {
+        log::info!("system_shared_memory_status: {:?}", request);
+        let name = &request.get_ref().name;
+        let shared_memory = self.shared_memory.lock().await;
+        shared_memory.system_status(name).map(tonic::Response::new)
+    }
+
+    async fn cuda_shared_memory_register(
+        &self,
+        request: tonic::Request<server::CudaSharedMemoryRegisterRequest>,
+    ) -> std::result::Result<tonic::Response<server::CudaSharedMemoryRegisterResponse>, tonic::Status>
+    {
+        log::info!("cuda_shared_memory_register: {:?}", request);
+        let mut shared_memory = self.shared_memory.lock().await;
+        shared_memory.register_cuda(request.get_ref())?;
+        Ok(tonic::Response::new(
+            server::CudaSharedMemoryRegisterResponse {},
+        ))
+    }
+
+    async fn system_shared_memory_register(
+        &self,
+        request: tonic::Request<server::SystemSharedMemoryRegisterRequest>,
+    ) -> std::result::Result<
+        tonic::Response<server::SystemSharedMemoryRegisterResponse>,
+        tonic::Status,
+    > {
+        log::info!("system_shared_memory_register: {:?}", request);
+        let mut shared_memory = self.shared_memory.lock().await;
+        shared_memory.register_system(request.get_ref())?;
+        Ok(tonic::Response::new(
+            server::SystemSharedMemoryRegisterResponse {},
+        ))
+    }
+
+    async fn cuda_shared_memory_unregister(
+        &self,
+        request: tonic::Request<server::CudaSharedMemoryUnregisterRequest>,
+    ) -> std::result::Result<
+        tonic::Response<server::CudaSharedMemoryUnregisterResponse>,
+        tonic::Status,
+    > {
+        log::info!("cuda_shared_memory_unregister: {:?}", request);
+        let name = &request.get_ref().name;
+        let mut shared_memory = self.shared_memory.lock().await;
+        shared_memory.unregister_cuda(name)?;
+        Ok(tonic::Response::new(
+            server::CudaSharedMemoryUnregisterResponse {},
+        ))
+    }
+
+    async fn system_shared_memory_unregister(
+        &self,
+        request: tonic::Request<server::SystemSharedMemoryUnregisterRequest>,
+    ) -> std::result::Result<
+        tonic::Response<server::SystemSharedMemoryUnregisterResponse>,
+        tonic::Status,
+    > {
+        log::info!("system_shared_memory_unregister: {:?}", request);
+        let name = &request.get_ref().name;
+        let mut shared_memory = self.shared_memory.lock().await;
+        shared_memory.unregister_system(name)?;
+        Ok(tonic::Response::new(
+            server::SystemSharedMemoryUnregisterResponse {},
+        ))
+    }
+}
+
+/// The command line of the `triton-mock` binary, which a [`MockServer`] can
+/// also be started from.
+#[derive(clap::Parser, Debug)]
+pub struct CliOptions {
+    #[clap(long)]
+    record: bool,
+    #[clap(long, default_value = "host.docker.internal")]
+    remote_host: String,
+    #[clap(long, default_value = "0")]
+    suffix: String,
+    /// gRPC listen addresses, each `<port>`, `<host>:<port>` or
+    /// `unix:<path>`; may be repeated. Port 0 picks a free port.
+    #[clap(long)]
+    grpc_listen: Vec<ListenAddress>,
+    #[clap(long, alias = "http-port", default_value = "8000")]
+    http_listen: ListenAddress,
+    #[clap(long, alias = "metrics-port", default_value = "8002")]
+    metrics_listen: ListenAddress,
+    /// Also serve an OpenAI-compatible API for the LLM models here.
+    #[clap(long, alias = "openai-port")]
+    openai_listen: Option<ListenAddress>,
+    /// Serve the admin API, which switches modes and manages the recording
+    /// at runtime, here.
+    #[clap(long)]
+    admin_listen: Option<ListenAddress>,
+    /// How many of the most recent requests the admin API keeps for
+    /// verification; 0 disables the journal.
+    #[clap(long, default_value = "1000")]
+    journal_size: usize,
+    /// Requests carrying this metadata header (or HTTP header) replay the
+    /// recording with cursors of their own, one set per header value.
+    #[clap(long, default_value = "x-triton-mock-session")]
+    session_header: String,
+    /// Also serve this model, e.g. one only stubs answer; may be repeated.
+    /// Models in the recording are served without it.
+    #[clap(long = "model")]
+    models: Vec<String>,
+    /// Directory of named cassettes, `<name>.json.gz`, that requests choose
+    /// with `--cassette-header`; loaded on first use.
+    #[clap(long)]
+    cassette_dir: Option<std::path::PathBuf>,
+    /// Metadata header (or HTTP header) naming the cassette a request replays
+    /// from or records into.
+    #[clap(long, default_value = "x-triton-mock-cassette")]
+    cassette_header: String,
+    /// Answer `model_infer` requests for models with a recorded config but
+    /// no recorded responses with responses fabricated from the config,
+    /// filled with `zeros`, `random[:<seed>]` or `constant:<value>`.
+    #[clap(long)]
+    synthetic: Option<Fill>,
+    /// Write the bound addresses to this JSON file once serving.
+    #[clap(long)]
+    ready_file: Option<String>,
+    /// Write the process id to this file; defaults to
+    /// `/tmp/triton-mock-server.pid` unless `--ready-file`, which holds the
+    /// pid as well, is given.
+    #[clap(long)]
+    pid_file: Option<String>,
+    /// Serve gRPC over TLS using `--grpc-server-cert` and `--grpc-server-key`.
+    #[clap(long)]
+    grpc_use_ssl: bool,
+    /// Require clients to present a certificate signed by `--grpc-root-cert`.
+    #[clap(long)]
+    grpc_use_ssl_mutual: bool,
+    #[clap(long)]
+    grpc_server_cert: Option<String>,
+    #[clap(long)]
+    grpc_server_key: Option<String>,
+    #[clap(long)]
+    grpc_root_cert: Option<String>,
+    /// CA certificate for the upstream Triton; connects over TLS when set.
+    #[clap(long)]
+    remote_ca_cert: Option<String>,
+    /// Client certificate and key presented to the upstream Triton.
+    #[clap(long, requires = "remote_client_key")]
+    remote_client_cert: Option<String>,
+    #[clap(long, requires = "remote_client_cert")]
+    remote_client_key: Option<String>,
+    /// Name to verify the upstream certificate against, if not `--remote-host`.
+    #[clap(long)]
+    remote_tls_domain: Option<String>,
+    /// Compress requests sent upstream in record mode.
+    #[clap(long, value_enum)]
+    remote_compression: Option<Compression>,
+    /// Largest gRPC message accepted, in bytes, on the listeners and from
+    /// upstream (tonic defaults to 4 MiB).
+    #[clap(long)]
+    max_decoding_message_size: Option<usize>,
+    /// Largest gRPC message sent, in bytes, on the listeners and upstream.
+    #[clap(long)]
+    max_encoding_message_size: Option<usize>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for CompressionEncoding {
+    fn from(compression: Compression) -> Self {
+        match compression {
+            Compression::Gzip => CompressionEncoding::Gzip,
+            Compression::Zstd => CompressionEncoding::Zstd,
+        }
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    std::fs::read(path).map_err(|e| format!("failed to read '{}': {}", path, e).into())
+}
+
+impl CliOptions {
+    /// Where the binary writes its process id, if anywhere.
+    pub fn pid_file(&self) -> Option<&str> {
+        match (&self.pid_file, &self.ready_file) {
+            (Some(pid_file), _) => Some(pid_file),
+            (None, None) => Some("/tmp/triton-mock-server.pid"),
+            (None, Some(_)) => None,
+        }
+    }
+
+    /// The TLS settings for the gRPC listeners, following Triton's
+    /// `--grpc-use-ssl*` options.
+    fn server_tls(&self) -> Result<Option<ServerTlsConfig>, Box<dyn Error>> {
+        if !self.grpc_use_ssl && !self.grpc_use_ssl_mutual {
+            return Ok(None);
+        }
+        let (Some(cert), Some(key)) = (&self.grpc_server_cert, &self.grpc_server_key) else {
+            return Err("--grpc-use-ssl requires --grpc-server-cert and --grpc-server-key".into());
+        };
+        let mut tls =
+            ServerTlsConfig::new().identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        if self.grpc_use_ssl_mutual {
+            let Some(root) = &self.grpc_root_cert else {
+                return Err("--grpc-use-ssl-mutual requires --grpc-root-cert".into());
+            };
+            tls = tls.client_ca_root(Certificate::from_pem(read_pem(root)?));
+        }
+        Ok(Some(tls))
+    }
+
+    /// Applies the message size limits to a listener's service, which accepts
+    /// gzip and zstd requests and compresses responses for clients that
+    /// accept either.
+    fn configure_server(
+        &self,
+        mut service: GrpcInferenceServiceServer<MockInferenceService>,
+    ) -> GrpcInferenceServiceServer<MockInferenceService> {
+        for encoding in [CompressionEncoding::Gzip, CompressionEncoding::Zstd] {
+            service = service
+                .accept_compressed(encoding)
+                .send_compressed(encoding);
+        }
+        if let Some(limit) = self.max_decoding_message_size {
+            service = service.max_decoding_message_size(limit);
+        }
+        if let Some(limit) = self.max_encoding_message_size {
+            service = service.max_encoding_message_size(limit);
+        }
+        service
+    }
+
+    fn configure_client(
+        &self,
+        mut client: GrpcInferenceServiceClient<Channel>,
+    ) -> GrpcInferenceServiceClient<Channel> {
+        for encoding in [CompressionEncoding::Gzip, CompressionEncoding::Zstd] {
+            client = client.accept_compressed(encoding);
+        }
+        if let Some(compression) = self.remote_compression {
+            client = client.send_compressed(compression.into());
+        }
+        if let Some(limit) = self.max_decoding_message_size {
+            client = client.max_decoding_message_size(limit);
+        }
+        if let Some(limit) = self.max_encoding_message_size {
+            client = client.max_encoding_message_size(limit);
+        }
+        client
+    }
+
+    /// The TLS settings for upstream connections in record mode.
+    fn remote_tls(&self) -> Result<Option<ClientTlsConfig>, Box<dyn Error>> {
+        let Some(ca) = &self.remote_ca_cert else {
+            return Ok(None);
+        };
+        let mut tls = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(read_pem(ca)?))
+            .domain_name(
+                self.remote_tls_domain
+                    .as_deref()
+                    .unwrap_or(&self.remote_host),
+            );
+        if let (Some(cert), Some(key)) = (&self.remote_client_cert, &self.remote_client_key) {
+            tls = tls.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+        }
+        Ok(Some(tls))
+    }
+}
+
+fn recording_filename(suffix: &str) -> String {
+    format!("triton-mock-recording-{}.json.gz", suffix)
+}
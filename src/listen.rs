@@ -0,0 +1,169 @@
+use std::{error::Error, future::Future, net::SocketAddr, path::PathBuf, pin::Pin, str::FromStr};
+
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+
+use tokio_stream::wrappers::{TcpListenerStream, UnixListenerStream};
+
+type ServeFuture = Pin<Box<dyn Future<Output = Result<(), Box<dyn Error + Send + Sync>>> + Send>>;
+
+/// Where a listener binds: a TCP address (port 0 lets the OS pick one) or a
+/// Unix domain socket, written `unix:<path>`. A bare port binds all
+/// interfaces.
+#[derive(Clone, Debug)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(ListenAddress::Unix(PathBuf::from(path)));
+        }
+        if let Ok(port) = s.parse::<u16>() {
+            return Ok(ListenAddress::Tcp(SocketAddr::from(([0, 0, 0, 0], port))));
+        }
+        s.parse().map(ListenAddress::Tcp).map_err(|_| {
+            format!(
+                "invalid listen address '{}', expected <port>, <host>:<port> or unix:<path>",
+                s
+            )
+        })
+    }
+}
+
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener, PathBuf),
+}
+
+impl ListenAddress {
+    /// Binds the address, replacing a stale Unix socket left over from an
+    /// earlier run. Anything else at a socket path is left alone.
+    pub async fn bind(&self) -> std::io::Result<Listener> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match self {
+            ListenAddress::Tcp(address) => TcpListener::bind(address).await.map(Listener::Tcp),
+            ListenAddress::Unix(path) => {
+                match std::fs::symlink_metadata(path) {
+                    Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+                    Ok(_) => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::AlreadyExists,
+                            format!("'{}' exists and is not a socket", path.display()),
+                        ))
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+                UnixListener::bind(path).map(|listener| Listener::Unix(listener, path.clone()))
+            }
+        }
+    }
+}
+
+impl Listener {
+    /// The address actually bound, with the OS-assigned port filled in.
+    pub fn local_address(&self) -> std::io::Result<ListenAddress> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr().map(ListenAddress::Tcp),
+            Listener::Unix(_, path) => Ok(ListenAddress::Unix(path.clone())),
+        }
+    }
+
+    pub fn serve_grpc(
+        self,
+        router: tonic::transport::server::Router,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> ServeFuture {
+        match self {
+            Listener::Tcp(listener) => Box::pin(async move {
+                router
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+                    .await
+                    .map_err(Into::into)
+            }),
+            Listener::Unix(listener, _) => Box::pin(async move {
+                router
+                    .serve_with_incoming_shutdown(UnixListenerStream::new(listener), shutdown)
+                    .await
+                    .map_err(Into::into)
+            }),
+        }
+    }
+
+    pub fn serve_http(
+        self,
+        router: axum::Router,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) -> ServeFuture {
+        use hyper::server::accept;
+
+        match self {
+            Listener::Tcp(listener) => Box::pin(async move {
+                axum::Server::builder(accept::from_stream(TcpListenerStream::new(listener)))
+                    .serve(router.into_make_service_with_connect_info::<Peer>())
+                    .with_graceful_shutdown(shutdown)
+                    .await
+                    .map_err(Into::into)
+            }),
+            Listener::Unix(listener, _) => Box::pin(async move {
+                axum::Server::builder(accept::from_stream(UnixListenerStream::new(listener)))
+                    .serve(router.into_make_service_with_connect_info::<Peer>())
+                    .with_graceful_shutdown(shutdown)
+                    .await
+                    .map_err(Into::into)
+            }),
+        }
+    }
+}
+
+/// The address an HTTP connection came from, made available to handlers as
+/// `ConnectInfo<Peer>`. Connections over a Unix socket have none.
+#[derive(Clone, Copy, Debug)]
+pub struct Peer(pub Option<SocketAddr>);
+
+impl axum::extract::connect_info::Connected<&TcpStream> for Peer {
+    fn connect_info(stream: &TcpStream) -> Self {
+        Peer(stream.peer_addr().ok())
+    }
+}
+
+impl axum::extract::connect_info::Connected<&UnixStream> for Peer {
+    fn connect_info(_: &UnixStream) -> Self {
+        Peer(None)
+    }
+}
+
+/// The addresses the mock is serving on, written once every listener is bound
+/// so test harnesses can discover OS-assigned ports.
+#[derive(serde::Serialize, Debug)]
+pub struct ReadyFile {
+    pub pid: u32,
+    pub grpc: Vec<String>,
+    pub http: String,
+    pub metrics: String,
+    pub openai: Option<String>,
+    pub admin: Option<String>,
+}
+
+impl ReadyFile {
+    /// Writes the file atomically, so readers never see it half written.
+    pub fn write(&self, path: &str) -> std::io::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(tmp, path)
+    }
+}
@@ -0,0 +1,349 @@
+use crate::server::{
+    log_settings_request, log_settings_response::setting_value::ParameterChoice,
+    log_settings_response::SettingValue, LogSettingsRequest, LogSettingsResponse,
+};
+
+use std::{
+    collections::HashMap,
+    io::Write,
+    sync::{Mutex, OnceLock, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tonic::Status;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LogFormat {
+    Default,
+    Iso8601,
+}
+
+impl LogFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogFormat::Default => "default",
+            LogFormat::Iso8601 => "ISO8601",
+        }
+    }
+}
+
+/// Mirrors the settings exposed by Triton's LogSettings RPC.
+#[derive(Clone, Debug)]
+struct LogSettings {
+    log_file: String,
+    log_info: bool,
+    log_warning: bool,
+    log_error: bool,
+    log_verbose_level: u32,
+    log_format: LogFormat,
+    /// Whether a LogSettings request has set the levels, letting the mock's
+    /// own records through beyond what `RUST_LOG` enables for them.
+    raised: bool,
+}
+
+impl LogSettings {
+    /// Seeds the settings from the most verbose level `filter` enables.
+    fn from_filter(filter: &env_logger::Logger) -> Self {
+        let level = filter.filter();
+        LogSettings {
+            log_file: String::new(),
+            log_info: level >= log::LevelFilter::Info,
+            log_warning: level >= log::LevelFilter::Warn,
+            log_error: level >= log::LevelFilter::Error,
+            log_verbose_level: match level {
+                log::LevelFilter::Trace => 2,
+                log::LevelFilter::Debug => 1,
+                _ => 0,
+            },
+            log_format: LogFormat::Default,
+            raised: false,
+        }
+    }
+
+    fn max_level(&self) -> log::LevelFilter {
+        if self.log_verbose_level >= 2 {
+            log::LevelFilter::Trace
+        } else if self.log_verbose_level == 1 {
+            log::LevelFilter::Debug
+        } else if self.log_info {
+            log::LevelFilter::Info
+        } else if self.log_warning {
+            log::LevelFilter::Warn
+        } else if self.log_error {
+            log::LevelFilter::Error
+        } else {
+            log::LevelFilter::Off
+        }
+    }
+
+    fn enabled(&self, level: log::Level) -> bool {
+        match level {
+            log::Level::Error => self.log_error,
+            log::Level::Warn => self.log_warning,
+            log::Level::Info => self.log_info,
+            log::Level::Debug => self.log_verbose_level >= 1,
+            log::Level::Trace => self.log_verbose_level >= 2,
+        }
+    }
+
+    fn to_response(&self) -> LogSettingsResponse {
+        let value = |choice| SettingValue {
+            parameter_choice: Some(choice),
+        };
+        let settings = HashMap::from([
+            (
+                "log_file".to_string(),
+                value(ParameterChoice::StringParam(self.log_file.clone())),
+            ),
+            (
+                "log_info".to_string(),
+                value(ParameterChoice::BoolParam(self.log_info)),
+            ),
+            (
+                "log_warning".to_string(),
+                value(ParameterChoice::BoolParam(self.log_warning)),
+            ),
+            (
+                "log_error".to_string(),
+                value(ParameterChoice::BoolParam(self.log_error)),
+            ),
+            (
+                "log_verbose_level".to_string(),
+                value(ParameterChoice::Uint32Param(self.log_verbose_level)),
+            ),
+            (
+                "log_format".to_string(),
+                value(ParameterChoice::StringParam(
+                    self.log_format.as_str().to_string(),
+                )),
+            ),
+        ]);
+        LogSettingsResponse { settings }
+    }
+}
+
+/// Logs through `RUST_LOG`'s per-target filter, with the levels set by
+/// LogSettings on top.
+struct MockLogger {
+    filter: env_logger::Logger,
+    settings: RwLock<LogSettings>,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl MockLogger {
+    fn allows(&self, settings: &LogSettings, metadata: &log::Metadata) -> bool {
+        let own = metadata.target().starts_with(env!("CARGO_CRATE_NAME"));
+        settings.enabled(metadata.level())
+            && ((own && settings.raised) || log::Log::enabled(&self.filter, metadata))
+    }
+}
+
+static LOGGER: OnceLock<MockLogger> = OnceLock::new();
+
+/// Converts days since the unix epoch to a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// A UTC wall-clock time broken into its civil fields.
+struct UtcTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    micros: u32,
+}
+
+impl UtcTime {
+    fn now() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let secs = now.as_secs() as i64;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let secs_of_day = secs.rem_euclid(86400);
+        UtcTime {
+            year,
+            month,
+            day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+            micros: now.subsec_micros(),
+        }
+    }
+}
+
+/// Formats the current time as an ISO 8601 UTC timestamp with microseconds.
+pub fn timestamp_now() -> String {
+    let t = UtcTime::now();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second, t.micros
+    )
+}
+
+fn format_record(format: LogFormat, record: &log::Record) -> String {
+    let t = UtcTime::now();
+    let severity = match record.level() {
+        log::Level::Error => 'E',
+        log::Level::Warn => 'W',
+        _ => 'I',
+    };
+    let location = match (record.file(), record.line()) {
+        (Some(file), Some(line)) => {
+            let file = file.rsplit('/').next().unwrap_or(file);
+            format!("{}:{}", file, line)
+        }
+        _ => record.target().to_string(),
+    };
+    match format {
+        LogFormat::Default => format!(
+            "{}{:02}{:02} {:02}:{:02}:{:02}.{:06} {} {}] {}\n",
+            severity,
+            t.month,
+            t.day,
+            t.hour,
+            t.minute,
+            t.second,
+            t.micros,
+            std::process::id(),
+            location,
+            record.args()
+        ),
+        LogFormat::Iso8601 => format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z {} {}] {}\n",
+            t.year,
+            t.month,
+            t.day,
+            t.hour,
+            t.minute,
+            t.second,
+            severity,
+            location,
+            record.args()
+        ),
+    }
+}
+
+impl log::Log for MockLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.allows(&self.settings.read().unwrap(), metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        let format = {
+            let settings = self.settings.read().unwrap();
+            if !self.allows(&settings, record.metadata()) {
+                return;
+            }
+            settings.log_format
+        };
+        let line = format_record(format, record);
+        let mut output = self.output.lock().unwrap();
+        let _ = output.write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = self.output.lock().unwrap().flush();
+    }
+}
+
+pub fn init() {
+    let filter = env_logger::Builder::from_default_env().build();
+    let settings = LogSettings::from_filter(&filter);
+    let max_level = settings.max_level();
+    let logger = LOGGER.get_or_init(|| MockLogger {
+        filter,
+        settings: RwLock::new(settings),
+        output: Mutex::new(Box::new(std::io::stderr())),
+    });
+    log::set_logger(logger).expect("logger already initialized");
+    log::set_max_level(max_level);
+}
+
+/// Applies a LogSettings request and returns the resulting settings. The
+/// request is validated as a whole before anything is changed.
+pub fn update_settings(request: &LogSettingsRequest) -> Result<LogSettingsResponse, Status> {
+    use log_settings_request::setting_value::ParameterChoice as Choice;
+
+    let logger = LOGGER
+        .get()
+        .ok_or_else(|| Status::internal("logger not initialized"))?;
+    // Held until the new settings are in place, so concurrent requests apply
+    // one after the other.
+    let mut current = logger.settings.write().unwrap();
+    let mut settings = current.clone();
+    for (key, value) in &request.settings {
+        let expected =
+            |kind: &str| Status::invalid_argument(format!("expect {} for '{}'", kind, key));
+        match (key.as_str(), &value.parameter_choice) {
+            ("log_file", Some(Choice::StringParam(v))) => settings.log_file = v.clone(),
+            ("log_file", _) => return Err(expected("string")),
+            ("log_info", Some(Choice::BoolParam(v))) => settings.log_info = *v,
+            ("log_warning", Some(Choice::BoolParam(v))) => settings.log_warning = *v,
+            ("log_error", Some(Choice::BoolParam(v))) => settings.log_error = *v,
+            ("log_info" | "log_warning" | "log_error", _) => return Err(expected("bool")),
+            ("log_verbose_level", Some(Choice::Uint32Param(v))) => settings.log_verbose_level = *v,
+            ("log_verbose_level", _) => return Err(expected("uint32")),
+            ("log_format", Some(Choice::StringParam(v))) => {
+                settings.log_format = match v.as_str() {
+                    "default" => LogFormat::Default,
+                    "ISO8601" => LogFormat::Iso8601,
+                    _ => {
+                        return Err(Status::invalid_argument(format!(
+                            "invalid argument for log_format, got: {}",
+                            v
+                        )))
+                    }
+                }
+            }
+            ("log_format", _) => return Err(expected("string")),
+            _ => {
+                return Err(Status::invalid_argument(format!(
+                    "Trying to set unsupported log setting: '{}'",
+                    key
+                )))
+            }
+        }
+    }
+    settings.raised |= request.settings.keys().any(|key| {
+        matches!(
+            key.as_str(),
+            "log_info" | "log_warning" | "log_error" | "log_verbose_level"
+        )
+    });
+    if settings.log_file != current.log_file {
+        let output: Box<dyn Write + Send> = if settings.log_file.is_empty() {
+            Box::new(std::io::stderr())
+        } else {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&settings.log_file)
+                .map_err(|e| {
+                    Status::invalid_argument(format!(
+                        "failed to open log file '{}': {}",
+                        settings.log_file, e
+                    ))
+                })?;
+            Box::new(file)
+        };
+        *logger.output.lock().unwrap() = output;
+    }
+    log::set_max_level(settings.max_level());
+    let response = settings.to_response();
+    *current = settings;
+    Ok(response)
+}
@@ -0,0 +1,238 @@
+use std::{collections::BTreeMap, fmt::Write, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+
+use tokio::sync::Mutex;
+
+/// Time spent in each phase of one inference request, reported the way Triton
+/// splits it in its duration metrics.
+#[derive(Debug, Default)]
+pub struct InferTimings {
+    pub queue: Duration,
+    pub compute_input: Duration,
+    pub compute_infer: Duration,
+    pub compute_output: Duration,
+}
+
+#[derive(Debug, Default)]
+struct ModelMetrics {
+    request_success: u64,
+    request_failure: u64,
+    inference_count: u64,
+    exec_count: u64,
+    request_duration_us: u64,
+    queue_duration_us: u64,
+    compute_input_duration_us: u64,
+    compute_infer_duration_us: u64,
+    compute_output_duration_us: u64,
+    pending_request_count: u64,
+}
+
+#[derive(Debug, Default)]
+struct ReplayMetrics {
+    hits: u64,
+    misses: u64,
+}
+
+/// Counters for the `nv_inference_*` families Triton exposes, labeled by
+/// model and version, plus the mock's own replay hit and miss counters.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    models: BTreeMap<(String, String), ModelMetrics>,
+    replay: BTreeMap<(String, String, &'static str), ReplayMetrics>,
+}
+
+/// A metric family: name, help text, type and how to read its value.
+type Family<T> = (&'static str, &'static str, &'static str, fn(&T) -> u64);
+
+/// Escapes a label value for the text exposition format, in which only
+/// backslash, double quote and line feed are special.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros() as u64
+}
+
+impl Metrics {
+    fn model(&mut self, model: &str, version: &str) -> &mut ModelMetrics {
+        self.models
+            .entry((model.to_string(), version.to_string()))
+            .or_default()
+    }
+
+    pub fn request_started(&mut self, model: &str, version: &str) {
+        self.model(model, version).pending_request_count += 1;
+    }
+
+    pub fn request_finished(&mut self, model: &str, version: &str) {
+        let metrics = self.model(model, version);
+        metrics.pending_request_count = metrics.pending_request_count.saturating_sub(1);
+    }
+
+    pub fn success(
+        &mut self,
+        model: &str,
+        version: &str,
+        batch_size: u64,
+        request_duration: Duration,
+        timings: &InferTimings,
+    ) {
+        let metrics = self.model(model, version);
+        metrics.request_success += 1;
+        metrics.inference_count += batch_size;
+        metrics.exec_count += 1;
+        metrics.request_duration_us += micros(request_duration);
+        metrics.queue_duration_us += micros(timings.queue);
+        metrics.compute_input_duration_us += micros(timings.compute_input);
+        metrics.compute_infer_duration_us += micros(timings.compute_infer);
+        metrics.compute_output_duration_us += micros(timings.compute_output);
+    }
+
+    pub fn failure(&mut self, model: &str, version: &str, request_duration: Duration) {
+        let metrics = self.model(model, version);
+        metrics.request_failure += 1;
+        metrics.request_duration_us += micros(request_duration);
+    }
+
+    /// Counts a replayed `rpc` that found (`hit`) or lacked a recorded response.
+    pub fn replay(&mut self, model: &str, version: &str, rpc: &'static str, hit: bool) {
+        let metrics = self
+            .replay
+            .entry((model.to_string(), version.to_string(), rpc))
+            .or_default();
+        if hit {
+            metrics.hits += 1;
+        } else {
+            metrics.misses += 1;
+        }
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let families: &[Family<ModelMetrics>] = &[
+            (
+                "nv_inference_request_success",
+                "Number of successful inference requests, all batch sizes",
+                "counter",
+                |m| m.request_success,
+            ),
+            (
+                "nv_inference_request_failure",
+                "Number of failed inference requests, all batch sizes",
+                "counter",
+                |m| m.request_failure,
+            ),
+            (
+                "nv_inference_count",
+                "Number of inferences performed (does not include cached requests)",
+                "counter",
+                |m| m.inference_count,
+            ),
+            (
+                "nv_inference_exec_count",
+                "Number of model executions performed (does not include cached requests)",
+                "counter",
+                |m| m.exec_count,
+            ),
+            (
+                "nv_inference_request_duration_us",
+                "Cumulative inference request duration in microseconds (includes cached requests)",
+                "counter",
+                |m| m.request_duration_us,
+            ),
+            (
+                "nv_inference_queue_duration_us",
+                "Cumulative inference queuing duration in microseconds (includes cached requests)",
+                "counter",
+                |m| m.queue_duration_us,
+            ),
+            (
+                "nv_inference_compute_input_duration_us",
+                "Cumulative compute input duration in microseconds (does not include cached requests)",
+                "counter",
+                |m| m.compute_input_duration_us,
+            ),
+            (
+                "nv_inference_compute_infer_duration_us",
+                "Cumulative compute inference duration in microseconds (does not include cached requests)",
+                "counter",
+                |m| m.compute_infer_duration_us,
+            ),
+            (
+                "nv_inference_compute_output_duration_us",
+                "Cumulative inference compute output duration in microseconds (does not include cached requests)",
+                "counter",
+                |m| m.compute_output_duration_us,
+            ),
+            (
+                "nv_inference_pending_request_count",
+                "Instantaneous number of pending requests awaiting execution per-model.",
+                "gauge",
+                |m| m.pending_request_count,
+            ),
+        ];
+        for (name, help, kind, value) in families {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for ((model, version), metrics) in &self.models {
+                let _ = writeln!(
+                    out,
+                    "{}{{model=\"{}\",version=\"{}\"}} {}",
+                    name,
+                    escape(model),
+                    escape(version),
+                    value(metrics)
+                );
+            }
+        }
+        let replay: &[Family<ReplayMetrics>] = &[
+            (
+                "triton_mock_replay_hits",
+                "Number of replayed requests answered from the recording",
+                "counter",
+                |m| m.hits,
+            ),
+            (
+                "triton_mock_replay_misses",
+                "Number of replayed requests with no recorded response left",
+                "counter",
+                |m| m.misses,
+            ),
+        ];
+        for (name, help, kind, value) in replay {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for ((model, version, rpc), metrics) in &self.replay {
+                let _ = writeln!(
+                    out,
+                    "{}{{model=\"{}\",version=\"{}\",rpc=\"{}\"}} {}",
+                    name,
+                    escape(model),
+                    escape(version),
+                    rpc,
+                    value(metrics)
+                );
+            }
+        }
+        out
+    }
+}
+
+async fn metrics(State(metrics): State<Arc<Mutex<Metrics>>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.lock().await.render(),
+    )
+}
+
+pub fn router(metrics: Arc<Mutex<Metrics>>) -> Router {
+    Router::new()
+        .route("/metrics", get(self::metrics))
+        .with_state(metrics)
+}
@@ -0,0 +1,462 @@
+use crate::{
+    admin,
+    cassette::Cassettes,
+    health::{proto::health_server::HealthServer, MockHealthService},
+    http,
+    journal::Journal,
+    listen::{ListenAddress, ReadyFile},
+    metrics::{self, Metrics},
+    openai,
+    scenario::Scenarios,
+    server::{self, grpc_inference_service_server::GrpcInferenceServiceServer},
+    shared_memory::SharedMemoryRegions,
+    stubs::Stubs,
+    synthetic::Fill,
+    trace::TraceManager,
+    upstream::{Mode, Upstream},
+    CliOptions, MockInferenceService, RecordedStreams, SERVER_PORTS,
+};
+
+use std::{error::Error, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+};
+
+use tonic::transport::Server;
+
+type ServeError = Box<dyn Error + Send + Sync>;
+
+/// Where the mock's recording comes from. Only a recording read from a file
+/// is saved back to it.
+#[derive(Debug)]
+enum Recording {
+    File(String),
+    Bytes(Vec<u8>),
+    Empty,
+}
+
+/// Configures and starts a [`MockServer`]. Every listener binds an ephemeral
+/// port on the loopback interface unless told otherwise, and the admin API is
+/// served, so tests can start as many isolated mocks as they need.
+#[derive(Debug)]
+pub struct MockServerBuilder {
+    options: CliOptions,
+    recording: Recording,
+    mode: Mode,
+}
+
+impl Default for MockServerBuilder {
+    fn default() -> Self {
+        use clap::Parser;
+
+        let ephemeral = ListenAddress::Tcp(SocketAddr::from(([127, 0, 0, 1], 0)));
+        let mut options = CliOptions::parse_from(["triton-mock"]);
+        options.grpc_listen = vec![ephemeral.clone()];
+        options.http_listen = ephemeral.clone();
+        options.metrics_listen = ephemeral.clone();
+        options.admin_listen = Some(ephemeral);
+        MockServerBuilder {
+            options,
+            recording: Recording::Empty,
+            mode: Mode::Replay,
+        }
+    }
+}
+
+impl MockServerBuilder {
+    /// Starts from the binary's command line: the recording named by
+    /// `--suffix`, recorded into with `--record`, on the listeners given.
+    pub fn from_options(options: CliOptions) -> Self {
+        MockServerBuilder {
+            recording: Recording::File(crate::recording_filename(&options.suffix)),
+            mode: if options.record {
+                Mode::Record
+            } else {
+                Mode::Replay
+            },
+            options,
+        }
+    }
+
+    /// Replays the recording in `path`, and in record mode saves what was
+    /// recorded there on shutdown.
+    pub fn recording_path(mut self, path: impl Into<String>) -> Self {
+        self.recording = Recording::File(path.into());
+        self
+    }
+
+    /// Replays a recording held in memory, gzip-compressed as on disk or plain
+    /// JSON. There is nowhere to save to, so it cannot be used in record mode.
+    pub fn recording_bytes(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.recording = Recording::Bytes(bytes.into());
+        self
+    }
+
+    /// The mode every model starts in; record and proxy connect to the
+    /// upstream Triton at `remote_host` on start.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn remote_host(mut self, host: impl Into<String>) -> Self {
+        self.options.remote_host = host.into();
+        self
+    }
+
+    /// Serves gRPC on `address` instead of an ephemeral port; may be called
+    /// again to add listeners.
+    pub fn grpc_listen(mut self, address: ListenAddress) -> Self {
+        if self.options.grpc_listen.iter().all(is_ephemeral) {
+            self.options.grpc_listen.clear();
+        }
+        self.options.grpc_listen.push(address);
+        self
+    }
+
+    pub fn http_listen(mut self, address: ListenAddress) -> Self {
+        self.options.http_listen = address;
+        self
+    }
+
+    pub fn metrics_listen(mut self, address: ListenAddress) -> Self {
+        self.options.metrics_listen = address;
+        self
+    }
+
+    pub fn openai_listen(mut self, address: ListenAddress) -> Self {
+        self.options.openai_listen = Some(address);
+        self
+    }
+
+    /// Serves the admin API on `address`, or not at all.
+    pub fn admin_listen(mut self, address: Option<ListenAddress>) -> Self {
+        self.options.admin_listen = address;
+        self
+    }
+
+    /// Serves named cassettes from `dir`, chosen by the cassette header.
+    pub fn cassette_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.cassette_dir = Some(dir.into());
+        self
+    }
+
+    /// Fabricates responses from the recorded config, filled as `fill`
+    /// says, for models without recorded `model_infer` responses.
+    pub fn synthetic(mut self, fill: Fill) -> Self {
+        self.options.synthetic = Some(fill);
+        self
+    }
+
+    /// Serves `name` besides the built-in models and those in the recording,
+    /// e.g. a model only stubs answer; may be called again to add more.
+    pub fn model(mut self, name: impl Into<String>) -> Self {
+        self.options.models.push(name.into());
+        self
+    }
+
+    /// Keeps the most recent `size` requests in the journal; 0 disables it.
+    pub fn journal_size(mut self, size: usize) -> Self {
+        self.options.journal_size = size;
+        self
+    }
+
+    /// Binds every listener and starts serving in the background.
+    pub async fn start(self) -> Result<MockServer, Box<dyn Error>> {
+        let MockServerBuilder {
+            options,
+            recording,
+            mode,
+        } = self;
+        if mode == Mode::Record && matches!(recording, Recording::Bytes(_)) {
+            return Err(
+                "a recording from memory cannot be recorded into, use recording_path".into(),
+            );
+        }
+        let options = Arc::new(options);
+        let server_tls = options.server_tls()?;
+
+        let upstream = Arc::new(Mutex::new(Upstream::new(options.clone())));
+        if mode != Mode::Replay {
+            options.remote_tls()?;
+            Upstream::set_mode(&upstream, None, mode).await?;
+        }
+        let mut recorded_streams = match &recording {
+            _ if mode == Mode::Record => RecordedStreams::default(),
+            Recording::File(path) => RecordedStreams::load(path)?,
+            Recording::Bytes(bytes) => RecordedStreams::from_bytes(bytes)?,
+            Recording::Empty => RecordedStreams::default(),
+        };
+        // Models recorded or proxied are served without a recording.
+        recorded_streams.loaded |= mode != Mode::Replay;
+        let recording_path = match recording {
+            Recording::File(path) => Some(path),
+            Recording::Bytes(_) | Recording::Empty => None,
+        };
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let shutdown = move || {
+            let mut shutdown_rx = shutdown_rx.clone();
+            async move {
+                let _ = shutdown_rx.wait_for(|stop| *stop).await;
+            }
+        };
+
+        let mut join_set = tokio::task::JoinSet::new();
+        let recorded_streams = Arc::new(Mutex::new(recorded_streams));
+        let shared_memory = Arc::new(Mutex::new(SharedMemoryRegions::default()));
+        let trace = Arc::new(Mutex::new(TraceManager::default()));
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        let stubs = Arc::new(Mutex::new(Stubs::default()));
+        let scenarios = Arc::new(Mutex::new(Scenarios::default()));
+        let journal = Arc::new(Mutex::new(Journal::new(options.journal_size)));
+        let session_header = options.session_header.to_ascii_lowercase();
+        let cassettes = Arc::new(Cassettes::new(
+            recorded_streams.clone(),
+            options.cassette_dir.clone(),
+            options.cassette_header.to_ascii_lowercase(),
+            options.models.clone(),
+        ));
+        let service = || {
+            MockInferenceService::new_with(
+                cassettes.clone(),
+                shared_memory.clone(),
+                trace.clone(),
+                metrics.clone(),
+                upstream.clone(),
+                stubs.clone(),
+                scenarios.clone(),
+                journal.clone(),
+                session_header.clone(),
+                options.synthetic,
+            )
+        };
+
+        let grpc_listen = if options.grpc_listen.is_empty() {
+            SERVER_PORTS
+                .iter()
+                .map(|port| port.parse())
+                .collect::<Result<Vec<ListenAddress>, _>>()?
+        } else {
+            options.grpc_listen.clone()
+        };
+        let mut unix_sockets = Vec::new();
+        let mut ready = ReadyFile {
+            pid: std::process::id(),
+            grpc: Vec::new(),
+            http: String::new(),
+            metrics: String::new(),
+            openai: None,
+            admin: None,
+        };
+
+        for address in &grpc_listen {
+            let reflection = tonic_reflection::server::Builder::configure()
+                .register_encoded_file_descriptor_set(server::FILE_DESCRIPTOR_SET)
+                .build()?;
+            let mut builder = Server::builder();
+            if let Some(tls) = &server_tls {
+                builder = builder.tls_config(tls.clone())?;
+            }
+            let router = builder
+                .add_service(options.configure_server(GrpcInferenceServiceServer::new(service())))
+                .add_service(reflection)
+                .add_service(HealthServer::new(MockHealthService::new_with(
+                    cassettes.clone(),
+                )));
+            let listener = address.bind().await?;
+            let bound = listener.local_address()?;
+            log::info!("Serving gRPC on {bound}");
+            if let ListenAddress::Unix(path) = &bound {
+                unix_sockets.push(path.clone());
+            }
+            ready.grpc.push(bound.to_string());
+            join_set.spawn(listener.serve_grpc(router, shutdown()));
+        }
+
+        let listener = options.http_listen.bind().await?;
+        let bound = listener.local_address()?;
+        log::info!("Serving HTTP on {bound}");
+        if let ListenAddress::Unix(path) = &bound {
+            unix_sockets.push(path.clone());
+        }
+        ready.http = bound.to_string();
+        join_set.spawn(listener.serve_http(http::router(Arc::new(service())), shutdown()));
+
+        let listener = options.metrics_listen.bind().await?;
+        let bound = listener.local_address()?;
+        log::info!("Serving metrics on {bound}");
+        if let ListenAddress::Unix(path) = &bound {
+            unix_sockets.push(path.clone());
+        }
+        ready.metrics = bound.to_string();
+        join_set.spawn(listener.serve_http(metrics::router(metrics.clone()), shutdown()));
+
+        if let Some(openai_listen) = &options.openai_listen {
+            let listener = openai_listen.bind().await?;
+            let bound = listener.local_address()?;
+            log::info!("Serving OpenAI-compatible API on {bound}");
+            if let ListenAddress::Unix(path) = &bound {
+                unix_sockets.push(path.clone());
+            }
+            ready.openai = Some(bound.to_string());
+            join_set.spawn(listener.serve_http(openai::router(Arc::new(service())), shutdown()));
+        }
+
+        if let Some(admin_listen) = &options.admin_listen {
+            let admin = Arc::new(admin::Admin::new_with(
+                cassettes.clone(),
+                upstream.clone(),
+                stubs.clone(),
+                scenarios.clone(),
+                journal.clone(),
+                recording_path.clone().unwrap_or_default(),
+            ));
+            let listener = admin_listen.bind().await?;
+            let bound = listener.local_address()?;
+            log::info!("Serving admin API on {bound}");
+            if let ListenAddress::Unix(path) = &bound {
+                unix_sockets.push(path.clone());
+            }
+            ready.admin = Some(bound.to_string());
+            join_set.spawn(listener.serve_http(admin::router(admin), shutdown()));
+        }
+
+        if let Some(ready_file) = &options.ready_file {
+            ready.write(ready_file)?;
+        }
+
+        // The server stops reporting ready as soon as shutdown is asked for,
+        // while requests in flight are still being answered.
+        let shutting_down = shutdown();
+        let closing = cassettes.clone();
+        tokio::spawn(async move {
+            shutting_down.await;
+            closing.close();
+        });
+
+        let ready_file = options.ready_file.clone();
+        let task = tokio::spawn(async move {
+            if let Some(res) = join_set.join_next().await {
+                log::warn!("Shutdown was signaled: {:?}", res);
+            }
+            cassettes.close();
+
+            join_set.abort_all();
+
+            for path in unix_sockets {
+                let _ = std::fs::remove_file(path);
+            }
+            if let Some(ready_file) = &ready_file {
+                let _ = std::fs::remove_file(ready_file);
+            }
+
+            let flushed = {
+                let mut trace = trace.lock().await;
+                trace.flush_all();
+                trace.flushed()
+            };
+            let _ = flushed.await;
+
+            // Only a mock started in record mode owns its recording files;
+            // anything recorded after switching modes at runtime is saved
+            // through the admin API.
+            let mut recorded_streams = recorded_streams.lock().await;
+            if mode == Mode::Record {
+                if let Some(path) = recording_path.filter(|_| recorded_streams.dirty) {
+                    recorded_streams.save(&path).map_err(|e| e.to_string())?;
+                    recorded_streams.dirty = false;
+                }
+                cassettes.save_all().await.map_err(|e| e.to_string())?;
+            } else {
+                if recorded_streams.dirty {
+                    log::warn!("discarding responses recorded into the recording without saving");
+                }
+                for name in cassettes.unsaved().await {
+                    log::warn!(
+                        "discarding responses recorded into cassette '{}' without saving",
+                        name
+                    );
+                }
+            }
+            Ok(())
+        });
+
+        Ok(MockServer {
+            ready,
+            shutdown: ShutdownHandle(Arc::new(shutdown_tx)),
+            task,
+        })
+    }
+}
+
+fn is_ephemeral(address: &ListenAddress) -> bool {
+    matches!(address, ListenAddress::Tcp(address) if address.port() == 0)
+}
+
+/// Stops a [`MockServer`] from anywhere, e.g. a signal handler.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle(Arc<watch::Sender<bool>>);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        let _ = self.0.send(true);
+    }
+}
+
+/// A mock Triton serving in the background of the current tokio runtime.
+#[derive(Debug)]
+pub struct MockServer {
+    ready: ReadyFile,
+    shutdown: ShutdownHandle,
+    task: JoinHandle<Result<(), ServeError>>,
+}
+
+impl MockServer {
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::default()
+    }
+
+    /// The first gRPC address bound, with the OS-assigned port filled in.
+    pub fn grpc_address(&self) -> &str {
+        &self.ready.grpc[0]
+    }
+
+    pub fn grpc_addresses(&self) -> &[String] {
+        &self.ready.grpc
+    }
+
+    pub fn http_address(&self) -> &str {
+        &self.ready.http
+    }
+
+    pub fn metrics_address(&self) -> &str {
+        &self.ready.metrics
+    }
+
+    pub fn openai_address(&self) -> Option<&str> {
+        self.ready.openai.as_deref()
+    }
+
+    pub fn admin_address(&self) -> Option<&str> {
+        self.ready.admin.as_deref()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Waits until the server stops, after a shutdown or a listener failing,
+    /// and has saved anything it recorded.
+    pub async fn wait(self) -> Result<(), ServeError> {
+        self.task.await?
+    }
+
+    /// Stops serving and waits until anything recorded has been saved.
+    pub async fn shutdown(self) -> Result<(), ServeError> {
+        self.shutdown.shutdown();
+        self.wait().await
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{
+    http::{self, Metadata},
+    journal::Caller,
+    server::{self, grpc_inference_service_server::GrpcInferenceService},
+    tensor, MockInferenceService,
+};
+
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{
+        sse::{Event, Sse},
+        IntoResponse, Response,
+    },
+    routing::post,
+    Json, Router,
+};
+
+use serde_json::{json, Map, Value};
+
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+type Service = Arc<MockInferenceService>;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn error_response(status: StatusCode, message: impl Into<String>) -> Response {
+    let kind = if status.is_client_error() {
+        "invalid_request_error"
+    } else {
+        "server_error"
+    };
+    (
+        status,
+        Json(json!({
+            "error": { "message": message.into(), "type": kind, "param": null, "code": null }
+        })),
+    )
+        .into_response()
+}
+
+fn status_response(status: tonic::Status) -> Response {
+    let code = match status.code() {
+        tonic::Code::NotFound => StatusCode::NOT_FOUND,
+        tonic::Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        tonic::Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        tonic::Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error_response(code, status.message())
+}
+
+#[derive(serde::Deserialize)]
+struct ChatMessage {
+    role: String,
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct CompletionRequest {
+    model: String,
+    #[serde(default)]
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    prompt: Option<Value>,
+    #[serde(default)]
+    stream: bool,
+    max_tokens: Option<u64>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    stop: Option<Value>,
+}
+
+impl CompletionRequest {
+    fn sampling_parameters(&self) -> Map<String, Value> {
+        let mut params = Map::new();
+        if let Some(v) = self.max_tokens {
+            params.insert("max_tokens".to_string(), Value::from(v));
+        }
+        if let Some(v) = self.temperature {
+            params.insert("temperature".to_string(), Value::from(v));
+        }
+        if let Some(v) = self.top_p {
+            params.insert("top_p".to_string(), Value::from(v));
+        }
+        if let Some(v) = &self.stop {
+            params.insert("stop".to_string(), v.clone());
+        }
+        params
+    }
+}
+
+/// Renders chat messages with the Llama 2 / Mistral instruct template, the
+/// format both recorded LLMs were prompted with.
+fn chat_prompt(messages: &[ChatMessage]) -> String {
+    let mut prompt = String::new();
+    let mut system = None;
+    for message in messages {
+        let content = message.content.as_deref().unwrap_or_default();
+        match message.role.as_str() {
+            "system" => system = Some(content),
+            "assistant" => {
+                prompt.push_str(content);
+                prompt.push_str("</s>");
+            }
+            _ => {
+                prompt.push_str("<s>[INST] ");
+                if let Some(system) = system.take() {
+                    prompt.push_str(&format!("<<SYS>>\n{}\n<</SYS>>\n\n", system));
+                }
+                prompt.push_str(content);
+                prompt.push_str(" [/INST]");
+            }
+        }
+    }
+    prompt
+}
+
+/// Maps an OpenAI request onto the inputs of a Triton LLM model. Optional
+/// inputs are only sent when the recorded model config declares them.
+async fn infer_request(
+    service: &MockInferenceService,
+    metadata: &Metadata,
+    body: &CompletionRequest,
+    prompt: String,
+) -> Result<server::ModelInferRequest, Response> {
+    let caller = Caller::from_request(&http::grpc_request(metadata, ()));
+    let config = service.recorded_config(&caller, &body.model, "").await;
+    let declares = |name: &str| {
+        config
+            .as_ref()
+            .is_some_and(|c| c.input.iter().any(|input| input.name == name))
+    };
+    let mut fields = Map::new();
+    fields.insert("text_input".to_string(), Value::from(prompt));
+    let sampling_parameters = body.sampling_parameters();
+    if declares("sampling_parameters") {
+        fields.insert(
+            "sampling_parameters".to_string(),
+            Value::from(Value::Object(sampling_parameters).to_string()),
+        );
+    } else {
+        for (key, value) in sampling_parameters {
+            let name = if key == "stop" { "stop_words" } else { &key };
+            if declares(name) {
+                fields.insert(name.to_string(), value);
+            }
+        }
+    }
+    if declares("stream") {
+        fields.insert("stream".to_string(), Value::from(body.stream));
+    }
+    if declares("exclude_input_in_output") {
+        fields.insert("exclude_input_in_output".to_string(), Value::from(true));
+    }
+    http::generate_request(body.model.clone(), String::new(), fields, config)
+        .map_err(|e| error_response(StatusCode::BAD_REQUEST, e))
+}
+
+/// The generated text of a response, taken from `text_output` or else the
+/// first `BYTES` output.
+fn response_text(resp: &server::ModelInferResponse) -> Result<String, String> {
+    let Some(index) = resp
+        .outputs
+        .iter()
+        .position(|output| output.name == "text_output")
+        .or_else(|| resp.outputs.iter().position(|o| o.datatype == "BYTES"))
+    else {
+        return Ok(String::new());
+    };
+    let output = &resp.outputs[index];
+    let values = match (&output.contents, resp.raw_output_contents.get(index)) {
+        (Some(contents), _) => tensor::contents_to_json(&output.datatype, contents),
+        (None, Some(raw)) => tensor::raw_to_json(&output.datatype, raw)?,
+        (None, None) => Vec::new(),
+    };
+    Ok(values
+        .iter()
+        .filter_map(Value::as_str)
+        .collect::<Vec<_>>()
+        .concat())
+}
+
+#[derive(Clone, Copy)]
+enum Endpoint {
+    Chat,
+    Completion,
+}
+
+impl Endpoint {
+    fn id(&self) -> String {
+        let prefix = match self {
+            Endpoint::Chat => "chatcmpl",
+            Endpoint::Completion => "cmpl",
+        };
+        format!("{}-{}", prefix, NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn object(&self, stream: bool) -> &'static str {
+        match (self, stream) {
+            (Endpoint::Chat, false) => "chat.completion",
+            (Endpoint::Chat, true) => "chat.completion.chunk",
+            (Endpoint::Completion, _) => "text_completion",
+        }
+    }
+
+    fn choice(&self, stream: bool, text: Option<&str>, finish_reason: Option<&str>) -> Value {
+        match (self, stream) {
+            (Endpoint::Chat, false) => json!({
+                "index": 0,
+                "message": { "role": "assistant", "content": text.unwrap_or_default() },
+                "finish_reason": finish_reason,
+                "logprobs": null,
+            }),
+            (Endpoint::Chat, true) => {
+                let delta = match text {
+                    Some(text) => json!({ "role": "assistant", "content": text }),
+                    None => json!({}),
+                };
+                json!({
+                    "index": 0,
+                    "delta": delta,
+                    "finish_reason": finish_reason,
+                    "logprobs": null,
+                })
+            }
+            (Endpoint::Completion, _) => json!({
+                "index": 0,
+                "text": text.unwrap_or_default(),
+                "finish_reason": finish_reason,
+                "logprobs": null,
+            }),
+        }
+    }
+}
+
+fn created() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+async fn complete(
+    service: Service,
+    endpoint: Endpoint,
+    metadata: Metadata,
+    body: CompletionRequest,
+) -> Response {
+    let prompt = match endpoint {
+        Endpoint::Chat => chat_prompt(&body.messages),
+        Endpoint::Completion => match &body.prompt {
+            Some(Value::String(prompt)) => prompt.clone(),
+            Some(Value::Array(prompts)) if prompts.len() == 1 && prompts[0].is_string() => {
+                prompts[0].as_str().unwrap().to_string()
+            }
+            _ => {
+                return error_response(
+                    StatusCode::BAD_REQUEST,
+                    "'prompt' must be a string or a list of one string",
+                )
+            }
+        },
+    };
+    let request = match infer_request(&service, &metadata, &body, prompt).await {
+        Ok(request) => request,
+        Err(resp) => return resp,
+    };
+    let id = endpoint.id();
+    let created = created();
+    let chunk = move |choice: Value| {
+        json!({
+            "id": id,
+            "object": endpoint.object(body.stream),
+            "created": created,
+            "model": body.model,
+            "choices": [choice],
+        })
+    };
+    if !body.stream {
+        let resp = match service
+            .model_infer(http::grpc_request(&metadata, request))
+            .await
+        {
+            Ok(resp) => resp.into_inner(),
+            Err(status) => return status_response(status),
+        };
+        return match response_text(&resp) {
+            Ok(text) => {
+                Json(chunk(endpoint.choice(false, Some(&text), Some("stop")))).into_response()
+            }
+            Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, e),
+        };
+    }
+    let responses = match service
+        .decoupled_infer(http::grpc_request(&metadata, request))
+        .await
+    {
+        Ok(responses) => responses,
+        Err(status) => return status_response(status),
+    };
+    let finish = chunk(endpoint.choice(true, None, Some("stop")));
+    let deltas = ReceiverStream::new(responses).map(move |resp| {
+        let text = resp
+            .map_err(|status| status.message().to_string())
+            .and_then(|resp| response_text(&resp.infer_response.unwrap_or_default()));
+        match text {
+            Ok(text) => chunk(endpoint.choice(true, Some(&text), None)),
+            Err(e) => json!({ "error": { "message": e, "type": "server_error" } }),
+        }
+    });
+    let events = deltas
+        .chain(tokio_stream::once(finish))
+        .map(|data| Ok::<_, Infallible>(Event::default().data(data.to_string())))
+        .chain(tokio_stream::once(Ok(Event::default().data("[DONE]"))));
+    Sse::new(events).into_response()
+}
+
+fn parse(body: &[u8]) -> Result<CompletionRequest, Response> {
+    serde_json::from_slice(body).map_err(|e| {
+        error_response(
+            StatusCode::BAD_REQUEST,
+            format!("failed to parse the request JSON: {}", e),
+        )
+    })
+}
+
+async fn chat_completions(
+    State(service): State<Service>,
+    metadata: Metadata,
+    body: axum::body::Bytes,
+) -> Response {
+    match parse(&body) {
+        Ok(body) => complete(service, Endpoint::Chat, metadata, body).await,
+        Err(resp) => resp,
+    }
+}
+
+async fn completions(
+    State(service): State<Service>,
+    metadata: Metadata,
+    body: axum::body::Bytes,
+) -> Response {
+    match parse(&body) {
+        Ok(body) => complete(service, Endpoint::Completion, metadata, body).await,
+        Err(resp) => resp,
+    }
+}
+
+/// An OpenAI-compatible frontend over the recorded LLM traffic, replaying
+/// `ModelInfer` for plain requests and `ModelStreamInfer` for streamed ones.
+pub fn router(service: Service) -> Router {
+    Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/v1/completions", post(completions))
+        .with_state(service)
+}
@@ -0,0 +1,242 @@
+use crate::stubs::{self, Rpc, StubError};
+
+use std::collections::BTreeMap;
+
+use serde_json::{json, Value};
+
+use tonic::Status;
+
+fn started() -> String {
+    "Started".to_string()
+}
+
+/// One step of a scenario: what replay answers a request with while the
+/// scenario is in `state`, and the state it moves to afterwards.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct StepSpec {
+    pub state: String,
+    pub rpc: Rpc,
+    pub model: String,
+    /// Only gate requests for this version; empty gates any version.
+    #[serde(default)]
+    pub version: String,
+    /// Replay from the recorded response at this index instead of the next
+    /// one in order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    /// Fail the request instead of replaying.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StubError>,
+    /// For `model_ready` steps, the readiness to report instead of the
+    /// model's own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ready: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_state: Option<String>,
+}
+
+/// A state machine, registered through the admin API, deciding which recorded
+/// responses replay may answer with, like WireMock scenarios.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct ScenarioSpec {
+    pub name: String,
+    #[serde(default = "started")]
+    pub initial_state: String,
+    pub steps: Vec<StepSpec>,
+}
+
+/// What a scenario lets replay answer a request with.
+#[derive(Clone, Debug)]
+pub enum Gate {
+    /// No scenario covers the request; replay as usual.
+    Open,
+    /// Replay, from the recorded response at the index if one is given.
+    Replay(Option<usize>),
+    Error(Status),
+    /// Report this readiness for a `model_ready` request.
+    Ready(bool),
+    /// A scenario covers the request, but no step allows it in the current
+    /// state.
+    Closed,
+}
+
+/// A scenario's move to the next state of the step that gated a request,
+/// made with [`Scenarios::advance`] once a response has been found for it.
+#[derive(Debug)]
+pub struct Transition {
+    scenario: String,
+    from: String,
+    to: String,
+}
+
+#[derive(Debug)]
+struct Scenario {
+    spec: ScenarioSpec,
+    state: String,
+    errors: Vec<Option<Status>>,
+}
+
+impl StepSpec {
+    fn covers(&self, rpc: Rpc, model: &str, version: &str) -> bool {
+        self.rpc == rpc
+            && self.model == model
+            && (self.version.is_empty() || self.version == version)
+    }
+}
+
+/// The scenarios registered through the admin API and the state each is in.
+#[derive(Debug, Default)]
+pub struct Scenarios {
+    scenarios: BTreeMap<String, Scenario>,
+}
+
+impl Scenarios {
+    /// Registers a scenario in its initial state, replacing one of the same
+    /// name.
+    pub fn add(&mut self, spec: ScenarioSpec) -> Result<(), String> {
+        if spec.name.is_empty() {
+            return Err("a scenario needs a 'name'".to_string());
+        }
+        let mut errors = Vec::new();
+        for step in &spec.steps {
+            if step.index.is_some() && step.error.is_some() {
+                return Err("'index' and 'error' are mutually exclusive".to_string());
+            }
+            if step.ready.is_some() && (step.rpc != Rpc::Ready || step.error.is_some()) {
+                return Err(
+                    "'ready' is only allowed on model_ready steps without 'error'".to_string(),
+                );
+            }
+            if step.index.is_some() && step.rpc == Rpc::Ready {
+                return Err("model_ready steps cannot replay an 'index'".to_string());
+            }
+            errors.push(match &step.error {
+                Some(error) => Some(Status::new(
+                    stubs::parse_code(&error.code)?,
+                    error.message.clone(),
+                )),
+                None => None,
+            });
+        }
+        log::info!(
+            "scenario '{}' registered in state '{}'",
+            spec.name,
+            spec.initial_state
+        );
+        self.scenarios.insert(
+            spec.name.clone(),
+            Scenario {
+                state: spec.initial_state.clone(),
+                spec,
+                errors,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.scenarios.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.scenarios.clear();
+    }
+
+    /// Moves every scenario back to its initial state.
+    pub fn reset(&mut self) {
+        for scenario in self.scenarios.values_mut() {
+            scenario.state = scenario.spec.initial_state.clone();
+        }
+    }
+
+    pub fn set_state(&mut self, name: &str, state: String) -> bool {
+        let Some(scenario) = self.scenarios.get_mut(name) else {
+            return false;
+        };
+        log::info!("scenario '{}' moved to state '{}'", name, state);
+        scenario.state = state;
+        true
+    }
+
+    /// The registered scenarios with the state each is in.
+    pub fn list(&self) -> Vec<Value> {
+        self.scenarios
+            .values()
+            .map(|scenario| {
+                let mut json = json!({ "state": scenario.state });
+                if let Ok(Value::Object(spec)) = serde_json::to_value(&scenario.spec) {
+                    json.as_object_mut().unwrap().extend(spec);
+                }
+                json
+            })
+            .collect()
+    }
+
+    /// Decides what replay may answer a request with. The first scenario
+    /// with a step for the request's RPC and model gates it with the step for
+    /// its current state. A failing step moves the scenario to the step's next
+    /// state at once; otherwise the move is returned, to be made only if
+    /// replay finds a response.
+    pub fn gate(&mut self, rpc: Rpc, model: &str, version: &str) -> (Gate, Option<Transition>) {
+        let Some(scenario) = self.scenarios.values_mut().find(|scenario| {
+            scenario
+                .spec
+                .steps
+                .iter()
+                .any(|step| step.covers(rpc, model, version))
+        }) else {
+            return (Gate::Open, None);
+        };
+        let Some(position) = scenario
+            .spec
+            .steps
+            .iter()
+            .position(|step| step.covers(rpc, model, version) && step.state == scenario.state)
+        else {
+            log::debug!(
+                "scenario '{}' in state '{}' has no step for {:?} of '{}'",
+                scenario.spec.name,
+                scenario.state,
+                rpc,
+                model
+            );
+            return (Gate::Closed, None);
+        };
+        let step = &scenario.spec.steps[position];
+        let transition = step.next_state.as_ref().map(|next_state| Transition {
+            scenario: scenario.spec.name.clone(),
+            from: scenario.state.clone(),
+            to: next_state.clone(),
+        });
+        match (&scenario.errors[position], step.ready) {
+            (Some(status), _) => {
+                let gate = Gate::Error(status.clone());
+                self.advance(transition);
+                (gate, None)
+            }
+            (None, Some(ready)) => (Gate::Ready(ready), transition),
+            (None, None) => (Gate::Replay(step.index), transition),
+        }
+    }
+
+    /// Moves a scenario on after the request it gated was answered, unless
+    /// it has left the state the request was gated in meanwhile.
+    pub fn advance(&mut self, transition: Option<Transition>) {
+        let Some(transition) = transition else {
+            return;
+        };
+        let Some(scenario) = self.scenarios.get_mut(&transition.scenario) else {
+            return;
+        };
+        if scenario.state != transition.from {
+            return;
+        }
+        log::info!(
+            "scenario '{}' moved from '{}' to '{}'",
+            transition.scenario,
+            transition.from,
+            transition.to
+        );
+        scenario.state = transition.to;
+    }
+}
@@ -0,0 +1,431 @@
+use crate::server::{
+    self, cuda_shared_memory_status_response, infer_parameter::ParameterChoice,
+    model_infer_request::InferRequestedOutputTensor,
+    system_shared_memory_status_response::RegionStatus, InferParameter,
+};
+
+use std::collections::HashMap;
+
+use tonic::Status;
+
+const SHM_REGION_PARAM: &str = "shared_memory_region";
+const SHM_OFFSET_PARAM: &str = "shared_memory_offset";
+const SHM_BYTE_SIZE_PARAM: &str = "shared_memory_byte_size";
+
+#[derive(Debug)]
+struct SystemSharedMemoryRegion {
+    key: String,
+    offset: u64,
+    byte_size: u64,
+    mmap: memmap2::MmapMut,
+}
+
+/// CUDA regions are only tracked, never opened: the mock has no GPU to
+/// import the IPC handle on.
+#[derive(Debug)]
+struct CudaSharedMemoryRegion {
+    device_id: u64,
+    byte_size: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SharedMemoryRegions {
+    system: HashMap<String, SystemSharedMemoryRegion>,
+    cuda: HashMap<String, CudaSharedMemoryRegion>,
+}
+
+/// A tensor's reference into a registered region, taken from its
+/// `shared_memory_*` parameters.
+#[derive(Debug, Clone)]
+pub struct SharedMemoryRef {
+    region: String,
+    offset: u64,
+    byte_size: Option<u64>,
+}
+
+fn param_as_u64(param: &InferParameter) -> Option<u64> {
+    match param.parameter_choice {
+        Some(ParameterChoice::Int64Param(v)) => u64::try_from(v).ok(),
+        Some(ParameterChoice::Uint64Param(v)) => Some(v),
+        _ => None,
+    }
+}
+
+impl SharedMemoryRef {
+    /// Removes the `shared_memory_*` parameters from `parameters`, returning
+    /// the reference they described, if any.
+    fn take_from(parameters: &mut HashMap<String, InferParameter>) -> Result<Option<Self>, Status> {
+        let region = match parameters.remove(SHM_REGION_PARAM) {
+            Some(InferParameter {
+                parameter_choice: Some(ParameterChoice::StringParam(region)),
+            }) => region,
+            Some(_) => {
+                return Err(Status::invalid_argument(format!(
+                    "invalid value type for '{}' parameter, expected string_param",
+                    SHM_REGION_PARAM
+                )))
+            }
+            None => return Ok(None),
+        };
+        let offset = parameters
+            .remove(SHM_OFFSET_PARAM)
+            .map(|p| param_as_u64(&p))
+            .unwrap_or(Some(0))
+            .ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "invalid value type for '{}' parameter, expected int64_param",
+                    SHM_OFFSET_PARAM
+                ))
+            })?;
+        let byte_size = match parameters.remove(SHM_BYTE_SIZE_PARAM) {
+            Some(p) => Some(param_as_u64(&p).ok_or_else(|| {
+                Status::invalid_argument(format!(
+                    "invalid value type for '{}' parameter, expected int64_param",
+                    SHM_BYTE_SIZE_PARAM
+                ))
+            })?),
+            None => None,
+        };
+        Ok(Some(SharedMemoryRef {
+            region,
+            offset,
+            byte_size,
+        }))
+    }
+}
+
+/// The file behind a POSIX shared memory key. As with `shm_open`, the key is
+/// a single name with an optional leading `/`; anything that could reach
+/// outside `/dev/shm` is rejected.
+fn shm_path(key: &str) -> Result<String, Status> {
+    let name = key.strip_prefix('/').unwrap_or(key);
+    if name.is_empty() || name.contains('/') || name.contains("..") {
+        return Err(Status::invalid_argument(format!(
+            "invalid shared memory key '{}'",
+            key
+        )));
+    }
+    Ok(format!("/dev/shm/{}", name))
+}
+
+/// Fails requests that reference shared memory on `model_stream_infer`, which
+/// only carries tensors in the messages themselves.
+pub fn reject_refs(request: &server::ModelInferRequest) -> Result<(), Status> {
+    let inputs = request.inputs.iter().map(|input| &input.parameters);
+    let outputs = request.outputs.iter().map(|output| &output.parameters);
+    if inputs
+        .chain(outputs)
+        .any(|parameters| parameters.contains_key(SHM_REGION_PARAM))
+    {
+        return Err(Status::invalid_argument(
+            "shared memory is not supported on model_stream_infer",
+        ));
+    }
+    Ok(())
+}
+
+impl SharedMemoryRegions {
+    fn ensure_unregistered(&self, name: &str) -> Result<(), Status> {
+        if self.system.contains_key(name) || self.cuda.contains_key(name) {
+            return Err(Status::already_exists(format!(
+                "shared memory region '{}' already in manager",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn register_system(
+        &mut self,
+        request: &server::SystemSharedMemoryRegisterRequest,
+    ) -> Result<(), Status> {
+        self.ensure_unregistered(&request.name)?;
+        let path = shm_path(&request.key)?;
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| {
+                log::error!("register_system: open '{}': {:?}", request.key, e);
+                Status::internal(format!(
+                    "Unable to open shared memory region: '{}'",
+                    request.key
+                ))
+            })?;
+        let byte_size = usize::try_from(request.byte_size)
+            .map_err(|_| Status::invalid_argument("byte_size too large"))?;
+        // Touching a mapping past the end of its file raises SIGBUS, so the
+        // region must fit in the file as it is now.
+        let file_size = file
+            .metadata()
+            .map_err(|e| {
+                log::error!("register_system: stat '{}': {:?}", request.key, e);
+                Status::internal(format!(
+                    "Unable to open shared memory region: '{}'",
+                    request.key
+                ))
+            })?
+            .len();
+        match request.offset.checked_add(request.byte_size) {
+            Some(end) if end <= file_size => {}
+            _ => {
+                return Err(Status::invalid_argument(format!(
+                    "shared memory region '{}' with offset {} and byte size {} does not fit \
+                     in the {} bytes of key '{}'",
+                    request.name, request.offset, request.byte_size, file_size, request.key
+                )))
+            }
+        }
+        // Safety: the mapping is shared with the client process by design;
+        // concurrent modification by the client is the documented contract of
+        // Triton's shared memory extension.
+        let mmap = unsafe {
+            memmap2::MmapOptions::new()
+                .offset(request.offset)
+                .len(byte_size)
+                .map_mut(&file)
+        }
+        .map_err(|e| {
+            log::error!("register_system: mmap '{}': {:?}", request.key, e);
+            Status::internal(format!(
+                "unable to process address for shared memory key '{}'",
+                request.key
+            ))
+        })?;
+        self.system.insert(
+            request.name.clone(),
+            SystemSharedMemoryRegion {
+                key: request.key.clone(),
+                offset: request.offset,
+                byte_size: request.byte_size,
+                mmap,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn system_status(
+        &self,
+        name: &str,
+    ) -> Result<server::SystemSharedMemoryStatusResponse, Status> {
+        let status = |(name, region): (&String, &SystemSharedMemoryRegion)| {
+            (
+                name.clone(),
+                RegionStatus {
+                    name: name.clone(),
+                    key: region.key.clone(),
+                    offset: region.offset,
+                    byte_size: region.byte_size,
+                },
+            )
+        };
+        let regions = if name.is_empty() {
+            self.system.iter().map(status).collect()
+        } else {
+            let region = self.system.get_key_value(name).ok_or_else(|| {
+                Status::not_found(format!(
+                    "Unable to find system shared memory region: '{}'",
+                    name
+                ))
+            })?;
+            HashMap::from([status(region)])
+        };
+        Ok(server::SystemSharedMemoryStatusResponse { regions })
+    }
+
+    pub fn unregister_system(&mut self, name: &str) -> Result<(), Status> {
+        if name.is_empty() {
+            self.system.clear();
+        } else if self.system.remove(name).is_none() {
+            return Err(Status::not_found(format!(
+                "Unable to find shared memory region: '{}'",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn register_cuda(
+        &mut self,
+        request: &server::CudaSharedMemoryRegisterRequest,
+    ) -> Result<(), Status> {
+        self.ensure_unregistered(&request.name)?;
+        let device_id = u64::try_from(request.device_id).map_err(|_| {
+            Status::invalid_argument(format!("invalid device id {}", request.device_id))
+        })?;
+        self.cuda.insert(
+            request.name.clone(),
+            CudaSharedMemoryRegion {
+                device_id,
+                byte_size: request.byte_size,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn cuda_status(
+        &self,
+        name: &str,
+    ) -> Result<server::CudaSharedMemoryStatusResponse, Status> {
+        let status = |(name, region): (&String, &CudaSharedMemoryRegion)| {
+            (
+                name.clone(),
+                cuda_shared_memory_status_response::RegionStatus {
+                    name: name.clone(),
+                    device_id: region.device_id,
+                    byte_size: region.byte_size,
+                },
+            )
+        };
+        let regions = if name.is_empty() {
+            self.cuda.iter().map(status).collect()
+        } else {
+            let region = self.cuda.get_key_value(name).ok_or_else(|| {
+                Status::not_found(format!(
+                    "Unable to find cuda shared memory region: '{}'",
+                    name
+                ))
+            })?;
+            HashMap::from([status(region)])
+        };
+        Ok(server::CudaSharedMemoryStatusResponse { regions })
+    }
+
+    pub fn unregister_cuda(&mut self, name: &str) -> Result<(), Status> {
+        if name.is_empty() {
+            self.cuda.clear();
+        } else if self.cuda.remove(name).is_none() {
+            return Err(Status::not_found(format!(
+                "Unable to find cuda shared memory region: '{}'",
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// The system region a tensor lives in. CUDA regions are refused first,
+    /// as the mock only emulates their registration.
+    fn system_region(&self, name: &str) -> Result<&SystemSharedMemoryRegion, Status> {
+        if self.cuda.contains_key(name) {
+            return Err(Status::unimplemented(format!(
+                "shared memory region '{}' is a CUDA region; triton-mock only emulates \
+                 CUDA shared memory registration and cannot serve tensors from it",
+                name
+            )));
+        }
+        self.system.get(name).ok_or_else(|| {
+            Status::not_found(format!("Unable to find shared memory region: '{}'", name))
+        })
+    }
+
+    fn region_bounds(
+        &self,
+        shm: &SharedMemoryRef,
+        byte_size: u64,
+    ) -> Result<(&SystemSharedMemoryRegion, std::ops::Range<usize>), Status> {
+        let region = self.system_region(&shm.region)?;
+        match shm.offset.checked_add(byte_size) {
+            Some(end) if end <= region.byte_size => Ok((region, shm.offset as usize..end as usize)),
+            _ => Err(Status::invalid_argument(format!(
+                "Invalid offset + byte size for shared memory region: '{}'",
+                shm.region
+            ))),
+        }
+    }
+
+    fn read(&self, shm: &SharedMemoryRef) -> Result<Vec<u8>, Status> {
+        self.system_region(&shm.region)?;
+        let byte_size = shm.byte_size.ok_or_else(|| {
+            Status::invalid_argument(format!(
+                "'{}' parameter is required for shared memory input in region '{}'",
+                SHM_BYTE_SIZE_PARAM, shm.region
+            ))
+        })?;
+        let (region, range) = self.region_bounds(shm, byte_size)?;
+        Ok(region.mmap[range].to_vec())
+    }
+
+    fn write(&mut self, shm: &SharedMemoryRef, name: &str, data: &[u8]) -> Result<(), Status> {
+        let capacity = shm.byte_size.unwrap_or(u64::MAX);
+        if (data.len() as u64) > capacity {
+            return Err(Status::invalid_argument(format!(
+                "shared memory size specified with the request for output '{}' ({} bytes) \
+                 should be at least {} bytes to hold the results",
+                name,
+                capacity,
+                data.len()
+            )));
+        }
+        let (_, range) = self.region_bounds(shm, data.len() as u64)?;
+        let region = self.system.get_mut(&shm.region).unwrap();
+        region.mmap[range].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Replaces every input that lives in a registered region with its bytes in
+    /// `raw_input_contents`, so the request looks exactly like one sent without
+    /// shared memory.
+    pub fn resolve_inputs(&self, request: &mut server::ModelInferRequest) -> Result<(), Status> {
+        let mut shm_inputs = Vec::new();
+        for (index, input) in request.inputs.iter_mut().enumerate() {
+            if let Some(shm) = SharedMemoryRef::take_from(&mut input.parameters)? {
+                shm_inputs.push((index, shm));
+            }
+        }
+        if shm_inputs.is_empty() {
+            return Ok(());
+        }
+        let mut raw = std::mem::take(&mut request.raw_input_contents).into_iter();
+        let mut shm_inputs = shm_inputs.into_iter().peekable();
+        for (index, input) in request.inputs.iter().enumerate() {
+            if let Some((_, shm)) = shm_inputs.next_if(|(i, _)| *i == index) {
+                request.raw_input_contents.push(self.read(&shm)?);
+            } else if input.contents.is_none() {
+                request
+                    .raw_input_contents
+                    .push(raw.next().unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies replayed output tensors into the regions the client asked for and
+    /// blanks their `raw_output_contents` entries, as Triton does.
+    pub fn write_outputs(
+        &mut self,
+        outputs: &[(String, SharedMemoryRef)],
+        response: &mut server::ModelInferResponse,
+    ) -> Result<(), Status> {
+        for (name, shm) in outputs {
+            let index = response
+                .outputs
+                .iter()
+                .position(|output| &output.name == name);
+            let Some(data) = index.and_then(|i| response.raw_output_contents.get_mut(i)) else {
+                log::warn!(
+                    "write_outputs: no raw contents for shared memory output '{}'",
+                    name
+                );
+                continue;
+            };
+            self.write(shm, name, data)?;
+            data.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Strips the `shared_memory_*` parameters from the requested outputs so the
+/// request can be forwarded upstream, returning where each output should be
+/// written once a response is available.
+pub fn take_output_refs(
+    outputs: &mut [InferRequestedOutputTensor],
+) -> Result<Vec<(String, SharedMemoryRef)>, Status> {
+    let mut refs = Vec::new();
+    for output in outputs {
+        if let Some(shm) = SharedMemoryRef::take_from(&mut output.parameters)? {
+            refs.push((output.name.clone(), shm));
+        }
+    }
+    Ok(refs)
+}
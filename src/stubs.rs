@@ -0,0 +1,343 @@
+use crate::{
+    server::{self, model_infer_response::InferOutputTensor},
+    tensor,
+};
+
+use std::{collections::BTreeMap, time::Duration};
+
+use serde_json::{json, Map, Value};
+
+use tonic::{Code, Status};
+
+/// The RPC a stub answers or a scenario step gates. `model_stream_infer` stubs answer each request on a
+/// stream, including the decoupled requests behind `generate_stream` and the
+/// OpenAI frontend.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Rpc {
+    #[serde(rename = "model_infer")]
+    Infer,
+    #[serde(rename = "model_config")]
+    Config,
+    #[serde(rename = "model_stream_infer")]
+    StreamInfer,
+    /// Only gated by scenarios; stubs cannot answer it.
+    #[serde(rename = "model_ready")]
+    Ready,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct StubError {
+    /// A gRPC status code name such as `UNAVAILABLE` or `INVALID_ARGUMENT`.
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+fn default_priority() -> i64 {
+    5
+}
+
+/// A stub as registered through the admin API: which requests it matches,
+/// what it answers them with, and how often.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct StubSpec {
+    pub rpc: Rpc,
+    pub model: String,
+    /// Only match requests for this version; empty matches any version.
+    #[serde(default)]
+    pub version: String,
+    /// Among matching stubs the lowest priority wins, then the most recently
+    /// registered one.
+    #[serde(default = "default_priority")]
+    pub priority: i64,
+    /// Input tensors the request must carry, with their data in the JSON
+    /// layout of the HTTP frontend; `null` matches any data.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub inputs: BTreeMap<String, Value>,
+    /// Request parameters the request must carry with these values.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub parameters: Map<String, Value>,
+    /// The response: outputs in the HTTP frontend's JSON layout for inference
+    /// RPCs, a `ModelConfigResponse` for `model_config`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<StubError>,
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// How many requests the stub answers before it stops matching.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub times: Option<u64>,
+}
+
+#[derive(Debug)]
+enum Reply {
+    Infer(server::ModelInferResponse),
+    Config(Box<server::ModelConfigResponse>),
+    Error(Status),
+}
+
+#[derive(Debug)]
+struct Stub {
+    id: u64,
+    spec: StubSpec,
+    reply: Reply,
+    hits: u64,
+}
+
+/// Stubs registered through the admin API. They are consulted before the
+/// recording or the upstream Triton, in every mode, and what they answer is
+/// never recorded.
+#[derive(Debug, Default)]
+pub struct Stubs {
+    stubs: Vec<Stub>,
+    next_id: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct OutputJson {
+    name: String,
+    datatype: String,
+    shape: Vec<i64>,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+    data: Value,
+}
+
+#[derive(serde::Deserialize)]
+struct InferResponseJson {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    parameters: Map<String, Value>,
+    outputs: Vec<OutputJson>,
+}
+
+/// Parses a gRPC status code name such as `UNAVAILABLE`, in any case.
+pub fn parse_code(name: &str) -> Result<Code, String> {
+    let wanted = name.replace('_', "").to_ascii_lowercase();
+    (0..=16)
+        .map(Code::from_i32)
+        .find(|code| format!("{:?}", code).to_ascii_lowercase() == wanted)
+        .ok_or_else(|| format!("unknown status code '{}'", name))
+}
+
+fn infer_response(value: Value) -> Result<server::ModelInferResponse, String> {
+    let body: InferResponseJson =
+        serde_json::from_value(value).map_err(|e| format!("invalid response: {}", e))?;
+    let mut resp = server::ModelInferResponse {
+        id: body.id,
+        parameters: tensor::json_to_parameters(&body.parameters)?,
+        ..Default::default()
+    };
+    for output in body.outputs {
+        let raw = tensor::json_to_raw(&output.datatype, &output.data)
+            .map_err(|e| format!("output '{}': {}", output.name, e))?;
+        resp.raw_output_contents.push(raw);
+        resp.outputs.push(InferOutputTensor {
+            name: output.name,
+            datatype: output.datatype,
+            shape: output.shape,
+            parameters: tensor::json_to_parameters(&output.parameters)?,
+            contents: None,
+        });
+    }
+    Ok(resp)
+}
+
+/// Whether a decoded tensor holds exactly the expected (possibly nested)
+/// JSON data, comparing numbers by value so `1` matches `1.0`.
+fn data_matches(expected: &Value, actual: &[Value]) -> bool {
+    let mut values = Vec::new();
+    tensor::flatten(expected, &mut values);
+    values.len() == actual.len()
+        && values.iter().zip(actual).all(|(expected, actual)| {
+            match (expected.as_f64(), actual.as_f64()) {
+                (Some(expected), Some(actual)) => expected == actual,
+                _ => *expected == actual,
+            }
+        })
+}
+
+impl Stub {
+    fn matches(
+        &self,
+        rpc: Rpc,
+        model: &str,
+        version: &str,
+        request: Option<&server::ModelInferRequest>,
+    ) -> bool {
+        let spec = &self.spec;
+        if spec.rpc != rpc
+            || spec.model != model
+            || (!spec.version.is_empty() && spec.version != version)
+            || spec.times.is_some_and(|times| self.hits >= times)
+        {
+            return false;
+        }
+        let Some(request) = request else {
+            return true;
+        };
+        let inputs_match = spec.inputs.iter().all(|(name, expected)| {
+            let Some(index) = request.inputs.iter().position(|input| &input.name == name) else {
+                return false;
+            };
+            expected.is_null()
+                || tensor::input_to_json(request, index)
+                    .is_ok_and(|actual| data_matches(expected, &actual))
+        });
+        let parameters_match = spec.parameters.iter().all(|(key, expected)| {
+            request
+                .parameters
+                .get(key)
+                .is_some_and(|param| tensor::parameter_to_json(param) == *expected)
+        });
+        inputs_match && parameters_match
+    }
+}
+
+impl Stubs {
+    /// Registers a stub, returning its id.
+    pub fn add(&mut self, spec: StubSpec) -> Result<u64, String> {
+        if spec.rpc == Rpc::Ready {
+            return Err("stubs cannot answer model_ready".to_string());
+        }
+        if spec.rpc == Rpc::Config && !(spec.inputs.is_empty() && spec.parameters.is_empty()) {
+            return Err("model_config stubs cannot match inputs or parameters".to_string());
+        }
+        let reply = match (&spec.response, &spec.error) {
+            (Some(_), Some(_)) => {
+                return Err("'response' and 'error' are mutually exclusive".to_string())
+            }
+            (None, None) => return Err("either 'response' or 'error' is required".to_string()),
+            (None, Some(error)) => {
+                Reply::Error(Status::new(parse_code(&error.code)?, error.message.clone()))
+            }
+            (Some(response), None) if spec.rpc == Rpc::Config => Reply::Config(
+                serde_json::from_value(response.clone())
+                    .map_err(|e| format!("invalid response: {}", e))?,
+            ),
+            (Some(response), None) => Reply::Infer(infer_response(response.clone())?),
+        };
+        self.next_id += 1;
+        let id = self.next_id;
+        log::info!(
+            "stub {} registered for {:?} of '{}'",
+            id,
+            spec.rpc,
+            spec.model
+        );
+        self.stubs.push(Stub {
+            id,
+            spec,
+            reply,
+            hits: 0,
+        });
+        Ok(id)
+    }
+
+    pub fn remove(&mut self, id: u64) -> bool {
+        let len = self.stubs.len();
+        self.stubs.retain(|stub| stub.id != id);
+        self.stubs.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.stubs.clear();
+    }
+
+    /// The registered stubs with how many requests each has answered.
+    pub fn list(&self) -> Vec<Value> {
+        self.stubs
+            .iter()
+            .map(|stub| {
+                let mut json = json!({ "id": stub.id, "hits": stub.hits });
+                if let Ok(Value::Object(spec)) = serde_json::to_value(&stub.spec) {
+                    json.as_object_mut().unwrap().extend(spec);
+                }
+                json
+            })
+            .collect()
+    }
+
+    /// Finds the stub answering a request and counts the hit.
+    fn take(
+        &mut self,
+        rpc: Rpc,
+        model: &str,
+        version: &str,
+        request: Option<&server::ModelInferRequest>,
+    ) -> Option<&Stub> {
+        let stub = self
+            .stubs
+            .iter_mut()
+            .rev()
+            .filter(|stub| stub.matches(rpc, model, version, request))
+            .min_by_key(|stub| stub.spec.priority)?;
+        stub.hits += 1;
+        log::debug!("stub {} answers {:?} of '{}'", stub.id, rpc, model);
+        Some(&*stub)
+    }
+
+    /// The id of the stub answering an inference request, its reply and the
+    /// delay before it, or `None` when no stub matches.
+    pub fn infer(
+        &mut self,
+        rpc: Rpc,
+        request: &server::ModelInferRequest,
+    ) -> Option<(u64, Duration, Result<server::ModelInferResponse, Status>)> {
+        let stub = self.take(
+            rpc,
+            &request.model_name,
+            &request.model_version,
+            Some(request),
+        )?;
+        let result = match &stub.reply {
+            Reply::Infer(resp) => Ok(server::ModelInferResponse {
+                model_name: request.model_name.clone(),
+                model_version: request.model_version.clone(),
+                id: if resp.id.is_empty() {
+                    request.id.clone()
+                } else {
+                    resp.id.clone()
+                },
+                ..resp.clone()
+            }),
+            Reply::Error(status) => Err(status.clone()),
+            Reply::Config(_) => unreachable!("config stubs only match model_config"),
+        };
+        Some((stub.id, Duration::from_millis(stub.spec.delay_ms), result))
+    }
+
+    /// The id of the stub answering a `model_config` request, its reply and
+    /// the delay before it.
+    pub fn config(
+        &mut self,
+        request: &server::ModelConfigRequest,
+    ) -> Option<(u64, Duration, Result<server::ModelConfigResponse, Status>)> {
+        let stub = self.take(Rpc::Config, &request.name, &request.version, None)?;
+        let result = match &stub.reply {
+            Reply::Config(resp) => Ok(*resp.clone()),
+            Reply::Error(status) => Err(status.clone()),
+            Reply::Infer(_) => unreachable!("inference stubs only match inference RPCs"),
+        };
+        Some((stub.id, Duration::from_millis(stub.spec.delay_ms), result))
+    }
+}
+
+/// Wraps a stubbed reply for a stream, reporting errors in the response the
+/// way Triton does instead of ending the stream.
+pub fn stream_response(
+    result: Result<server::ModelInferResponse, Status>,
+) -> server::ModelStreamInferResponse {
+    match result {
+        Ok(resp) => server::ModelStreamInferResponse {
+            error_message: String::new(),
+            infer_response: Some(resp),
+        },
+        Err(status) => server::ModelStreamInferResponse {
+            error_message: status.message().to_string(),
+            infer_response: None,
+        },
+    }
+}
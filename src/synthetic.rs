@@ -0,0 +1,165 @@
+use crate::{
+    server::{self, model_infer_response::InferOutputTensor},
+    tensor::{self, bf16, f16, Element},
+};
+
+use std::str::FromStr;
+
+use tonic::Status;
+
+/// The values synthetic responses are filled with: `zeros`, `random` or
+/// `random:<seed>` (floats in `[0, 1)`, integers in `[0, 128)`), or
+/// `constant:<value>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fill {
+    Zeros,
+    Random(u64),
+    Constant(f64),
+}
+
+impl FromStr for Fill {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid fill '{}', expected zeros, random[:<seed>] or constant:<value>",
+                s
+            )
+        };
+        match s.split_once(':') {
+            None if s == "zeros" => Ok(Fill::Zeros),
+            None if s == "random" => Ok(Fill::Random(0)),
+            Some(("random", seed)) => seed.parse().map(Fill::Random).map_err(|_| invalid()),
+            Some(("constant", value)) => value.parse().map(Fill::Constant).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl std::fmt::Display for Fill {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Fill::Zeros => write!(f, "zeros"),
+            Fill::Random(seed) => write!(f, "random:{}", seed),
+            Fill::Constant(value) => write!(f, "constant:{}", value),
+        }
+    }
+}
+
+/// SplitMix64, seeded per output so every output of every response is the
+/// same for a given seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64, output: &str) -> Self {
+        let hash = output.bytes().fold(0xcbf29ce484222325u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+        Rng(seed ^ hash)
+    }
+
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// The raw contents of a `datatype` output of `count` elements.
+fn fill_raw(datatype: &str, count: usize, fill: Fill, output: &str) -> Result<Vec<u8>, String> {
+    let mut rng = Rng::new(
+        match fill {
+            Fill::Random(seed) => seed,
+            _ => 0,
+        },
+        output,
+    );
+    let integral = !matches!(datatype, "FP16" | "BF16" | "FP32" | "FP64");
+    let values: Vec<f64> = (0..count)
+        .map(|_| match fill {
+            Fill::Zeros => 0.0,
+            Fill::Constant(value) => value,
+            Fill::Random(_) if datatype == "BOOL" => (rng.next() * 2.0).floor(),
+            Fill::Random(_) if integral => (rng.next() * 128.0).floor(),
+            Fill::Random(_) => rng.next(),
+        })
+        .collect();
+    let shape = [count as i64];
+    fn raw<T: Element>(shape: &[i64], values: impl Iterator<Item = T>) -> Result<Vec<u8>, String> {
+        tensor::to_raw(shape, &values.collect::<Vec<T>>())
+    }
+    let values = values.into_iter();
+    match datatype {
+        "BOOL" => raw(&shape, values.map(|v| v != 0.0)),
+        "INT8" => raw(&shape, values.map(|v| v as i8)),
+        "INT16" => raw(&shape, values.map(|v| v as i16)),
+        "INT32" => raw(&shape, values.map(|v| v as i32)),
+        "INT64" => raw(&shape, values.map(|v| v as i64)),
+        "UINT8" => raw(&shape, values.map(|v| v as u8)),
+        "UINT16" => raw(&shape, values.map(|v| v as u16)),
+        "UINT32" => raw(&shape, values.map(|v| v as u32)),
+        "UINT64" => raw(&shape, values.map(|v| v as u64)),
+        "FP16" => raw(&shape, values.map(f16::from_f64)),
+        "BF16" => raw(&shape, values.map(bf16::from_f64)),
+        "FP32" => raw(&shape, values.map(|v| v as f32)),
+        "FP64" => raw(&shape, values),
+        "BYTES" => raw(&shape, values.map(|v| v.to_string())),
+        _ => Err(format!("unsupported datatype '{}'", datatype)),
+    }
+}
+
+/// Fabricates a response to `request` from the outputs of a model's config,
+/// for models with a recorded config but no recorded responses. Outputs take
+/// the config's dims, with variable dims as 1, behind the request's batch
+/// size for models that batch; only the requested outputs are returned if
+/// the request names any. Batches larger than the config's `max_batch_size`
+/// are rejected as Triton rejects them.
+pub fn response(
+    config: &server::ModelConfig,
+    request: &server::ModelInferRequest,
+    version: &str,
+    fill: Fill,
+) -> Result<server::ModelInferResponse, Status> {
+    let batch = request
+        .inputs
+        .first()
+        .and_then(|input| input.shape.first())
+        .map_or(1, |dim| (*dim).max(1));
+    if config.max_batch_size > 0 && batch > config.max_batch_size as i64 {
+        return Err(Status::invalid_argument(format!(
+            "inference request batch-size must be <= {} for '{}'",
+            config.max_batch_size, config.name
+        )));
+    }
+    let mut resp = server::ModelInferResponse {
+        model_name: config.name.clone(),
+        model_version: version.to_string(),
+        id: request.id.clone(),
+        ..Default::default()
+    };
+    for output in &config.output {
+        if !request.outputs.is_empty() && !request.outputs.iter().any(|o| o.name == output.name) {
+            continue;
+        }
+        let datatype = tensor::config_datatype(output.data_type);
+        let mut shape: Vec<i64> = output.dims.iter().map(|dim| (*dim).max(1)).collect();
+        if config.max_batch_size > 0 {
+            shape.insert(0, batch);
+        }
+        let count = tensor::element_count(&shape).map_err(Status::internal)?;
+        let raw = fill_raw(&datatype, count, fill, &output.name)
+            .map_err(|e| Status::internal(format!("synthetic output '{}': {}", output.name, e)))?;
+        resp.outputs.push(InferOutputTensor {
+            name: output.name.clone(),
+            datatype,
+            shape,
+            ..Default::default()
+        });
+        resp.raw_output_contents.push(raw);
+    }
+    Ok(resp)
+}
@@ -0,0 +1,708 @@
+use crate::server::{
+    infer_parameter::ParameterChoice, model_infer_request::InferInputTensor,
+    model_infer_response::InferOutputTensor, InferParameter, InferTensorContents,
+    ModelInferRequest, ModelInferResponse,
+};
+
+use std::collections::HashMap;
+
+pub use half::{bf16, f16};
+
+use serde_json::Value;
+
+/// Size in bytes of one element of a fixed-size Triton datatype, `None` for
+/// `BYTES` and unknown datatypes.
+pub fn element_size(datatype: &str) -> Option<usize> {
+    match datatype {
+        "BOOL" | "INT8" | "UINT8" => Some(1),
+        "INT16" | "UINT16" | "FP16" | "BF16" => Some(2),
+        "INT32" | "UINT32" | "FP32" => Some(4),
+        "INT64" | "UINT64" | "FP64" => Some(8),
+        _ => None,
+    }
+}
+
+/// Collects the elements of (possibly nested) JSON tensor data in row-major
+/// order.
+pub fn flatten<'a>(value: &'a Value, out: &mut Vec<&'a Value>) {
+    match value {
+        Value::Array(values) => values.iter().for_each(|v| flatten(v, out)),
+        value => out.push(value),
+    }
+}
+
+/// Encodes the (possibly nested) JSON `data` of a tensor into the little-endian
+/// layout used by `raw_input_contents`.
+pub fn json_to_raw(datatype: &str, data: &Value) -> Result<Vec<u8>, String> {
+    let mut values = Vec::new();
+    flatten(data, &mut values);
+    let mut raw = Vec::with_capacity(values.len() * element_size(datatype).unwrap_or(8));
+    let invalid = |v: &Value| format!("unable to parse '{}' as {}", v, datatype);
+    for v in values {
+        macro_rules! int {
+            ($t:ty, $get:ident) => {{
+                let n = v.$get().ok_or_else(|| invalid(v))?;
+                raw.extend_from_slice(&<$t>::try_from(n).map_err(|_| invalid(v))?.to_le_bytes());
+            }};
+        }
+        match datatype {
+            "BOOL" => raw.push(v.as_bool().ok_or_else(|| invalid(v))? as u8),
+            "INT8" => int!(i8, as_i64),
+            "INT16" => int!(i16, as_i64),
+            "INT32" => int!(i32, as_i64),
+            "INT64" => int!(i64, as_i64),
+            "UINT8" => int!(u8, as_u64),
+            "UINT16" => int!(u16, as_u64),
+            "UINT32" => int!(u32, as_u64),
+            "UINT64" => int!(u64, as_u64),
+            "FP32" => {
+                let n = v.as_f64().ok_or_else(|| invalid(v))?;
+                raw.extend_from_slice(&(n as f32).to_le_bytes());
+            }
+            "FP64" => raw.extend_from_slice(&v.as_f64().ok_or_else(|| invalid(v))?.to_le_bytes()),
+            "BYTES" => {
+                let s = v.as_str().ok_or_else(|| invalid(v))?;
+                raw.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                raw.extend_from_slice(s.as_bytes());
+            }
+            "FP16" | "BF16" => {
+                return Err(format!(
+                    "{} tensors are not supported in JSON, use binary data",
+                    datatype
+                ))
+            }
+            _ => return Err(format!("invalid datatype '{}'", datatype)),
+        }
+    }
+    Ok(raw)
+}
+
+/// Splits a serialized `BYTES` tensor into its length-prefixed elements.
+pub fn split_bytes(raw: &[u8]) -> Result<Vec<&[u8]>, String> {
+    let mut elements = Vec::new();
+    let mut rest = raw;
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err("truncated BYTES element length".to_string());
+        }
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        if rest.len() < 4 + len {
+            return Err("truncated BYTES element".to_string());
+        }
+        elements.push(&rest[4..4 + len]);
+        rest = &rest[4 + len..];
+    }
+    Ok(elements)
+}
+
+/// Decodes a tensor's `raw_output_contents` into a flat JSON array.
+pub fn raw_to_json(datatype: &str, raw: &[u8]) -> Result<Vec<Value>, String> {
+    macro_rules! le {
+        ($t:ty) => {
+            raw.chunks_exact(std::mem::size_of::<$t>())
+                .map(|c| Value::from(<$t>::from_le_bytes(c.try_into().unwrap())))
+                .collect()
+        };
+    }
+    if let Some(size) = element_size(datatype) {
+        if !raw.len().is_multiple_of(size) {
+            return Err(format!(
+                "{} bytes is not a whole number of {} elements",
+                raw.len(),
+                datatype
+            ));
+        }
+    }
+    Ok(match datatype {
+        "BOOL" => raw.iter().map(|b| Value::from(*b != 0)).collect(),
+        "INT8" => le!(i8),
+        "INT16" => le!(i16),
+        "INT32" => le!(i32),
+        "INT64" => le!(i64),
+        "UINT8" => le!(u8),
+        "UINT16" => le!(u16),
+        "UINT32" => le!(u32),
+        "UINT64" => le!(u64),
+        "FP32" => le!(f32),
+        "FP64" => le!(f64),
+        "BYTES" => split_bytes(raw)?
+            .into_iter()
+            .map(|b| Value::from(String::from_utf8_lossy(b)))
+            .collect(),
+        "FP16" | "BF16" => {
+            return Err(format!(
+                "{} tensors are not supported in JSON, use binary data",
+                datatype
+            ))
+        }
+        _ => return Err(format!("invalid datatype '{}'", datatype)),
+    })
+}
+
+/// Decodes typed `contents` into a flat JSON array.
+pub fn contents_to_json(datatype: &str, contents: &InferTensorContents) -> Vec<Value> {
+    fn values<T: Clone + Into<Value>>(v: &[T]) -> Vec<Value> {
+        v.iter().cloned().map(Into::into).collect()
+    }
+    match datatype {
+        "BOOL" => values(&contents.bool_contents),
+        "INT8" | "INT16" | "INT32" => values(&contents.int_contents),
+        "INT64" => values(&contents.int64_contents),
+        "UINT8" | "UINT16" | "UINT32" => values(&contents.uint_contents),
+        "UINT64" => values(&contents.uint64_contents),
+        "FP32" => values(&contents.fp32_contents),
+        "FP64" => values(&contents.fp64_contents),
+        "BYTES" => contents
+            .bytes_contents
+            .iter()
+            .map(|b| Value::from(String::from_utf8_lossy(b)))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Decodes the input tensor at `index` of a request, whether it is carried in
+/// `raw_input_contents` or typed `contents`, into a flat JSON array.
+pub fn input_to_json(
+    request: &crate::server::ModelInferRequest,
+    index: usize,
+) -> Result<Vec<Value>, String> {
+    let input = &request.inputs[index];
+    match (&input.contents, request.raw_input_contents.get(index)) {
+        (Some(contents), _) => Ok(contents_to_json(&input.datatype, contents)),
+        (None, Some(raw)) => raw_to_json(&input.datatype, raw),
+        (None, None) => Ok(Vec::new()),
+    }
+}
+
+pub fn parameter_to_json(param: &InferParameter) -> Value {
+    match &param.parameter_choice {
+        Some(ParameterChoice::BoolParam(v)) => Value::from(*v),
+        Some(ParameterChoice::Int64Param(v)) => Value::from(*v),
+        Some(ParameterChoice::StringParam(v)) => Value::from(v.as_str()),
+        Some(ParameterChoice::DoubleParam(v)) => Value::from(*v),
+        Some(ParameterChoice::Uint64Param(v)) => Value::from(*v),
+        None => Value::Null,
+    }
+}
+
+pub fn json_to_parameter(value: &Value) -> Result<InferParameter, String> {
+    let choice = match value {
+        Value::Bool(v) => ParameterChoice::BoolParam(*v),
+        Value::String(v) => ParameterChoice::StringParam(v.clone()),
+        Value::Number(n) => {
+            if let Some(v) = n.as_i64() {
+                ParameterChoice::Int64Param(v)
+            } else if let Some(v) = n.as_u64() {
+                ParameterChoice::Uint64Param(v)
+            } else {
+                ParameterChoice::DoubleParam(n.as_f64().unwrap_or_default())
+            }
+        }
+        _ => return Err(format!("unsupported parameter value {}", value)),
+    };
+    Ok(InferParameter {
+        parameter_choice: Some(choice),
+    })
+}
+
+pub fn parameters_to_json(
+    params: &HashMap<String, InferParameter>,
+) -> serde_json::Map<String, Value> {
+    params
+        .iter()
+        .map(|(key, param)| (key.clone(), parameter_to_json(param)))
+        .collect()
+}
+
+pub fn json_to_parameters(
+    params: &serde_json::Map<String, Value>,
+) -> Result<HashMap<String, InferParameter>, String> {
+    params
+        .iter()
+        .map(|(key, value)| Ok((key.clone(), json_to_parameter(value)?)))
+        .collect()
+}
+
+/// Converts a model config `DataType` to the datatype string used on the
+/// wire, e.g. `TYPE_FP32` to `FP32` and `TYPE_STRING` to `BYTES`.
+pub fn config_datatype(data_type: i32) -> String {
+    let name = crate::server::DataType::try_from(data_type)
+        .unwrap_or(crate::server::DataType::TypeInvalid)
+        .as_str_name();
+    match name.trim_start_matches("TYPE_") {
+        "STRING" => "BYTES".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// A Rust type holding the elements of tensors of one Triton datatype:
+/// `bool`, the integer types, [`f16`] for `FP16`, [`bf16`] for `BF16`, `f32`, `f64`, and `Vec<u8>` or `String` for `BYTES`.
+pub trait Element: Sized {
+    const DATATYPE: &'static str;
+
+    /// Appends `values` to `raw` in the layout of raw tensor contents.
+    fn encode(values: &[Self], raw: &mut Vec<u8>);
+
+    fn decode(raw: &[u8]) -> Result<Vec<Self>, String>;
+
+    /// `values` as typed contents; `FP16` and `BF16` have none and can only
+    /// be sent as raw contents.
+    fn to_contents(values: &[Self]) -> Result<InferTensorContents, String>;
+
+    fn from_contents(contents: &InferTensorContents) -> Result<Vec<Self>, String>;
+}
+
+fn decode_fixed<T, const N: usize>(
+    raw: &[u8],
+    datatype: &str,
+    from_le_bytes: fn([u8; N]) -> T,
+) -> Result<Vec<T>, String> {
+    if !raw.len().is_multiple_of(N) {
+        return Err(format!(
+            "{} bytes is not a whole number of {} elements",
+            raw.len(),
+            datatype
+        ));
+    }
+    Ok(raw
+        .chunks_exact(N)
+        .map(|c| from_le_bytes(c.try_into().unwrap()))
+        .collect())
+}
+
+fn no_contents(datatype: &str) -> String {
+    format!(
+        "{} tensors have no typed contents, use raw contents",
+        datatype
+    )
+}
+
+macro_rules! element {
+    ($t:ty, $datatype:literal, $field:ident: $wide:ty) => {
+        impl Element for $t {
+            const DATATYPE: &'static str = $datatype;
+
+            fn encode(values: &[Self], raw: &mut Vec<u8>) {
+                values
+                    .iter()
+                    .for_each(|v| raw.extend_from_slice(&v.to_le_bytes()));
+            }
+
+            fn decode(raw: &[u8]) -> Result<Vec<Self>, String> {
+                decode_fixed(raw, $datatype, <$t>::from_le_bytes)
+            }
+
+            fn to_contents(values: &[Self]) -> Result<InferTensorContents, String> {
+                Ok(InferTensorContents {
+                    $field: values.iter().map(|v| <$wide>::from(*v)).collect(),
+                    ..Default::default()
+                })
+            }
+
+            fn from_contents(contents: &InferTensorContents) -> Result<Vec<Self>, String> {
+                contents
+                    .$field
+                    .iter()
+                    .map(|v| {
+                        <$t>::try_from(*v)
+                            .map_err(|_| format!("{} is out of range for {}", v, $datatype))
+                    })
+                    .collect()
+            }
+        }
+    };
+    ($t:ty, $datatype:literal) => {
+        impl Element for $t {
+            const DATATYPE: &'static str = $datatype;
+
+            fn encode(values: &[Self], raw: &mut Vec<u8>) {
+                values
+                    .iter()
+                    .for_each(|v| raw.extend_from_slice(&v.to_le_bytes()));
+            }
+
+            fn decode(raw: &[u8]) -> Result<Vec<Self>, String> {
+                decode_fixed(raw, $datatype, <$t>::from_le_bytes)
+            }
+
+            fn to_contents(_: &[Self]) -> Result<InferTensorContents, String> {
+                Err(no_contents($datatype))
+            }
+
+            fn from_contents(_: &InferTensorContents) -> Result<Vec<Self>, String> {
+                Err(no_contents($datatype))
+            }
+        }
+    };
+}
+
+element!(i8, "INT8", int_contents: i32);
+element!(i16, "INT16", int_contents: i32);
+element!(i32, "INT32", int_contents: i32);
+element!(i64, "INT64", int64_contents: i64);
+element!(u8, "UINT8", uint_contents: u32);
+element!(u16, "UINT16", uint_contents: u32);
+element!(u32, "UINT32", uint_contents: u32);
+element!(u64, "UINT64", uint64_contents: u64);
+element!(f32, "FP32", fp32_contents: f32);
+element!(f64, "FP64", fp64_contents: f64);
+element!(f16, "FP16");
+element!(bf16, "BF16");
+
+impl Element for bool {
+    const DATATYPE: &'static str = "BOOL";
+
+    fn encode(values: &[Self], raw: &mut Vec<u8>) {
+        raw.extend(values.iter().map(|v| *v as u8));
+    }
+
+    fn decode(raw: &[u8]) -> Result<Vec<Self>, String> {
+        Ok(raw.iter().map(|b| *b != 0).collect())
+    }
+
+    fn to_contents(values: &[Self]) -> Result<InferTensorContents, String> {
+        Ok(InferTensorContents {
+            bool_contents: values.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn from_contents(contents: &InferTensorContents) -> Result<Vec<Self>, String> {
+        Ok(contents.bool_contents.clone())
+    }
+}
+
+impl Element for Vec<u8> {
+    const DATATYPE: &'static str = "BYTES";
+
+    fn encode(values: &[Self], raw: &mut Vec<u8>) {
+        for v in values {
+            raw.extend_from_slice(&(v.len() as u32).to_le_bytes());
+            raw.extend_from_slice(v);
+        }
+    }
+
+    fn decode(raw: &[u8]) -> Result<Vec<Self>, String> {
+        Ok(split_bytes(raw)?.into_iter().map(<[u8]>::to_vec).collect())
+    }
+
+    fn to_contents(values: &[Self]) -> Result<InferTensorContents, String> {
+        Ok(InferTensorContents {
+            bytes_contents: values.to_vec(),
+            ..Default::default()
+        })
+    }
+
+    fn from_contents(contents: &InferTensorContents) -> Result<Vec<Self>, String> {
+        Ok(contents.bytes_contents.clone())
+    }
+}
+
+impl Element for String {
+    const DATATYPE: &'static str = "BYTES";
+
+    fn encode(values: &[Self], raw: &mut Vec<u8>) {
+        for v in values {
+            raw.extend_from_slice(&(v.len() as u32).to_le_bytes());
+            raw.extend_from_slice(v.as_bytes());
+        }
+    }
+
+    fn decode(raw: &[u8]) -> Result<Vec<Self>, String> {
+        split_bytes(raw)?
+            .into_iter()
+            .map(|b| String::from_utf8(b.to_vec()).map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    fn to_contents(values: &[Self]) -> Result<InferTensorContents, String> {
+        Ok(InferTensorContents {
+            bytes_contents: values.iter().map(|v| v.clone().into_bytes()).collect(),
+            ..Default::default()
+        })
+    }
+
+    fn from_contents(contents: &InferTensorContents) -> Result<Vec<Self>, String> {
+        contents
+            .bytes_contents
+            .iter()
+            .map(|b| String::from_utf8(b.clone()).map_err(|e| e.to_string()))
+            .collect()
+    }
+}
+
+/// The number of elements in a tensor of `shape`.
+pub fn element_count(shape: &[i64]) -> Result<usize, String> {
+    shape.iter().try_fold(1usize, |count, &dim| {
+        usize::try_from(dim)
+            .ok()
+            .and_then(|dim| count.checked_mul(dim))
+            .ok_or_else(|| format!("invalid shape {:?}", shape))
+    })
+}
+
+/// Checks that a tensor of `shape` holds `count` elements.
+pub fn check_count(shape: &[i64], count: usize) -> Result<(), String> {
+    let expected = element_count(shape)?;
+    if count != expected {
+        return Err(format!(
+            "shape {:?} needs {} elements, got {}",
+            shape, expected, count
+        ));
+    }
+    Ok(())
+}
+
+fn check_datatype<T: Element>(datatype: &str) -> Result<(), String> {
+    if datatype != T::DATATYPE {
+        return Err(format!(
+            "{} tensor cannot be read as {}",
+            datatype,
+            T::DATATYPE
+        ));
+    }
+    Ok(())
+}
+
+/// Encodes `values` as the raw contents of a tensor of `shape`.
+pub fn to_raw<T: Element>(shape: &[i64], values: &[T]) -> Result<Vec<u8>, String> {
+    check_count(shape, values.len())?;
+    let mut raw = Vec::with_capacity(values.len() * element_size(T::DATATYPE).unwrap_or(8));
+    T::encode(values, &mut raw);
+    Ok(raw)
+}
+
+/// Decodes the raw contents of a `datatype` tensor of `shape`.
+pub fn from_raw<T: Element>(datatype: &str, shape: &[i64], raw: &[u8]) -> Result<Vec<T>, String> {
+    check_datatype::<T>(datatype)?;
+    let values = T::decode(raw)?;
+    check_count(shape, values.len())?;
+    Ok(values)
+}
+
+/// Encodes `values` as the typed contents of a tensor of `shape`.
+pub fn to_contents<T: Element>(shape: &[i64], values: &[T]) -> Result<InferTensorContents, String> {
+    check_count(shape, values.len())?;
+    T::to_contents(values)
+}
+
+/// Decodes the typed contents of a `datatype` tensor of `shape`.
+pub fn from_contents<T: Element>(
+    datatype: &str,
+    shape: &[i64],
+    contents: &InferTensorContents,
+) -> Result<Vec<T>, String> {
+    check_datatype::<T>(datatype)?;
+    let values = T::from_contents(contents)?;
+    check_count(shape, values.len())?;
+    Ok(values)
+}
+
+/// An input tensor holding `values`, and its raw contents, for
+/// `inputs` and `raw_input_contents` of a request.
+pub fn input<T: Element>(
+    name: &str,
+    shape: &[i64],
+    values: &[T],
+) -> Result<(InferInputTensor, Vec<u8>), String> {
+    let raw = to_raw(shape, values).map_err(|e| format!("input '{}': {}", name, e))?;
+    let tensor = InferInputTensor {
+        name: name.to_string(),
+        datatype: T::DATATYPE.to_string(),
+        shape: shape.to_vec(),
+        ..Default::default()
+    };
+    Ok((tensor, raw))
+}
+
+/// An output tensor holding `values`, and its raw contents, for `outputs`
+/// and `raw_output_contents` of a response.
+pub fn output<T: Element>(
+    name: &str,
+    shape: &[i64],
+    values: &[T],
+) -> Result<(InferOutputTensor, Vec<u8>), String> {
+    let raw = to_raw(shape, values).map_err(|e| format!("output '{}': {}", name, e))?;
+    let tensor = InferOutputTensor {
+        name: name.to_string(),
+        datatype: T::DATATYPE.to_string(),
+        shape: shape.to_vec(),
+        ..Default::default()
+    };
+    Ok((tensor, raw))
+}
+
+fn read<T: Element>(
+    datatype: &str,
+    shape: &[i64],
+    contents: Option<&InferTensorContents>,
+    raw: Option<&Vec<u8>>,
+) -> Result<Vec<T>, String> {
+    match (contents, raw) {
+        (_, Some(raw)) => from_raw(datatype, shape, raw),
+        (Some(contents), None) => from_contents(datatype, shape, contents),
+        (None, None) => from_raw(datatype, shape, &[]),
+    }
+}
+
+/// The shape and values of the input `name` of a request, whether carried in
+/// raw or typed contents.
+pub fn request_input<T: Element>(
+    request: &ModelInferRequest,
+    name: &str,
+) -> Result<(Vec<i64>, Vec<T>), String> {
+    let (index, input) = request
+        .inputs
+        .iter()
+        .enumerate()
+        .find(|(_, input)| input.name == name)
+        .ok_or_else(|| format!("no input '{}'", name))?;
+    let values = read(
+        &input.datatype,
+        &input.shape,
+        input.contents.as_ref(),
+        request.raw_input_contents.get(index),
+    )
+    .map_err(|e| format!("input '{}': {}", name, e))?;
+    Ok((input.shape.clone(), values))
+}
+
+/// The shape and values of the output `name` of a response, whether carried
+/// in raw or typed contents.
+pub fn response_output<T: Element>(
+    response: &ModelInferResponse,
+    name: &str,
+) -> Result<(Vec<i64>, Vec<T>), String> {
+    let (index, output) = response
+        .outputs
+        .iter()
+        .enumerate()
+        .find(|(_, output)| output.name == name)
+        .ok_or_else(|| format!("no output '{}'", name))?;
+    let values = read(
+        &output.datatype,
+        &output.shape,
+        output.contents.as_ref(),
+        response.raw_output_contents.get(index),
+    )
+    .map_err(|e| format!("output '{}': {}", name, e))?;
+    Ok((output.shape.clone(), values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Element + Clone + PartialEq + std::fmt::Debug>(values: Vec<T>) {
+        let shape = [values.len() as i64];
+        let raw = to_raw(&shape, &values).unwrap();
+        assert_eq!(from_raw::<T>(T::DATATYPE, &shape, &raw).unwrap(), values);
+        if let Ok(contents) = to_contents(&shape, &values) {
+            assert_eq!(
+                from_contents::<T>(T::DATATYPE, &shape, &contents).unwrap(),
+                values
+            );
+        }
+    }
+
+    #[test]
+    fn every_element_round_trips() {
+        round_trip(vec![true, false, true]);
+        round_trip(vec![i8::MIN, 0, i8::MAX]);
+        round_trip(vec![i16::MIN, 0, i16::MAX]);
+        round_trip(vec![i32::MIN, 0, i32::MAX]);
+        round_trip(vec![i64::MIN, 0, i64::MAX]);
+        round_trip(vec![0u8, u8::MAX]);
+        round_trip(vec![0u16, u16::MAX]);
+        round_trip(vec![0u32, u32::MAX]);
+        round_trip(vec![0u64, u64::MAX]);
+        round_trip(vec![f16::from_f32(1.5), f16::from_f32(-2.0)]);
+        round_trip(vec![bf16::from_f32(1.5), bf16::from_f32(-2.0)]);
+        round_trip(vec![1.5f32, -2.0]);
+        round_trip(vec![1.5f64, -2.0]);
+        round_trip(vec!["hé".to_string(), String::new()]);
+        round_trip(vec![vec![0u8, 255], vec![]]);
+    }
+
+    #[test]
+    fn raw_layout_is_little_endian() {
+        assert_eq!(to_raw(&[1], &[1i32]).unwrap(), [1, 0, 0, 0]);
+        assert_eq!(to_raw(&[1], &[f16::ONE]).unwrap(), [0x00, 0x3c]);
+        assert_eq!(to_raw(&[1], &[bf16::ONE]).unwrap(), [0x80, 0x3f]);
+        assert_eq!(
+            to_raw(&[1], &["ab".to_string()]).unwrap(),
+            [2, 0, 0, 0, b'a', b'b']
+        );
+    }
+
+    #[test]
+    fn half_precision_has_no_typed_contents() {
+        assert!(to_contents(&[1], &[f16::ONE]).is_err());
+        assert!(to_contents(&[1], &[bf16::ONE]).is_err());
+    }
+
+    #[test]
+    fn counts_must_match_shapes() {
+        assert!(to_raw(&[2, 2], &[1i32, 2, 3]).is_err());
+        assert!(to_contents(&[3], &[1i32]).is_err());
+        assert!(from_raw::<i32>("INT32", &[2], &[0; 4]).is_err());
+        assert!(from_raw::<i32>("INT32", &[1], &[0; 3]).is_err());
+        assert!(element_count(&[-1, 2]).is_err());
+        assert_eq!(element_count(&[]).unwrap(), 1);
+        assert_eq!(element_count(&[2, 0]).unwrap(), 0);
+    }
+
+    #[test]
+    fn datatypes_must_match_elements() {
+        let raw = to_raw(&[1], &[1i32]).unwrap();
+        assert!(from_raw::<u32>("INT32", &[1], &raw).is_err());
+        assert!(from_raw::<f32>("INT32", &[1], &raw).is_err());
+    }
+
+    #[test]
+    fn truncated_bytes_are_rejected() {
+        assert!(from_raw::<String>("BYTES", &[1], &[5, 0, 0, 0, b'a']).is_err());
+        assert!(from_raw::<Vec<u8>>("BYTES", &[1], &[1, 0]).is_err());
+        assert!(split_bytes(&[1, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn narrow_integers_are_range_checked() {
+        let contents = InferTensorContents {
+            int_contents: vec![300],
+            ..Default::default()
+        };
+        assert!(from_contents::<i8>("INT8", &[1], &contents).is_err());
+        assert_eq!(
+            from_contents::<i16>("INT16", &[1], &contents).unwrap(),
+            [300]
+        );
+    }
+
+    #[test]
+    fn tensors_are_read_back_by_name() {
+        let (input, raw) = input("a", &[2], &[1i64, 2]).unwrap();
+        let request = ModelInferRequest {
+            inputs: vec![input],
+            raw_input_contents: vec![raw],
+            ..Default::default()
+        };
+        assert_eq!(
+            request_input::<i64>(&request, "a").unwrap(),
+            (vec![2], vec![1, 2])
+        );
+        assert!(request_input::<i64>(&request, "b").is_err());
+
+        let (mut output, _) = output("b", &[2], &[true, false]).unwrap();
+        output.contents = Some(to_contents(&[2], &[true, false]).unwrap());
+        let response = ModelInferResponse {
+            outputs: vec![output],
+            ..Default::default()
+        };
+        assert_eq!(
+            response_output::<bool>(&response, "b").unwrap(),
+            (vec![2], vec![true, false])
+        );
+    }
+}
@@ -0,0 +1,522 @@
+use crate::server::{
+    self, trace_setting_request, trace_setting_response, InferTensorContents, TraceSettingResponse,
+};
+
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    sync::mpsc::{self, Receiver, Sender},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::oneshot;
+use tonic::Status;
+
+const TRACE_FILE: &str = "trace_file";
+const TRACE_LEVEL: &str = "trace_level";
+const TRACE_RATE: &str = "trace_rate";
+const TRACE_COUNT: &str = "trace_count";
+const LOG_FREQUENCY: &str = "log_frequency";
+const TRACE_MODE: &str = "trace_mode";
+
+type Settings = BTreeMap<String, Vec<String>>;
+
+fn default_settings() -> Settings {
+    BTreeMap::from([
+        (TRACE_FILE.to_string(), vec![String::new()]),
+        (TRACE_LEVEL.to_string(), vec!["OFF".to_string()]),
+        (TRACE_RATE.to_string(), vec!["1000".to_string()]),
+        (TRACE_COUNT.to_string(), vec!["-1".to_string()]),
+        (LOG_FREQUENCY.to_string(), vec!["0".to_string()]),
+        (TRACE_MODE.to_string(), vec!["triton".to_string()]),
+    ])
+}
+
+fn validate(key: &str, value: &[String]) -> Result<(), Status> {
+    let single = || match value {
+        [v] => Ok(v.as_str()),
+        _ => Err(Status::invalid_argument(format!(
+            "expecting only 1 value for '{}'",
+            key
+        ))),
+    };
+    match key {
+        TRACE_FILE | TRACE_MODE => {
+            single()?;
+        }
+        TRACE_LEVEL => {
+            for level in value {
+                if !matches!(level.as_str(), "OFF" | "TIMESTAMPS" | "TENSORS") {
+                    return Err(Status::invalid_argument(format!(
+                        "expecting value for trace level to be OFF, TIMESTAMPS or TENSORS, got '{}'",
+                        level
+                    )));
+                }
+            }
+        }
+        TRACE_RATE | LOG_FREQUENCY => {
+            single()?.parse::<u32>().map_err(|_| {
+                Status::invalid_argument(format!("unable to parse '{}', got: {:?}", key, value))
+            })?;
+        }
+        TRACE_COUNT => {
+            single()?.parse::<i32>().map_err(|_| {
+                Status::invalid_argument(format!("unable to parse '{}', got: {:?}", key, value))
+            })?;
+        }
+        _ => {
+            return Err(Status::invalid_argument(format!(
+                "unsupported trace setting: '{}'",
+                key
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// The effective settings for one model, parsed for use on the request path.
+#[derive(Debug)]
+struct EffectiveSettings {
+    file: String,
+    timestamps: bool,
+    tensors: bool,
+    rate: u32,
+    count: i32,
+    log_frequency: u32,
+}
+
+impl EffectiveSettings {
+    fn parse(settings: &Settings) -> Self {
+        let first = |key| settings[key].first().cloned().unwrap_or_default();
+        let level = &settings[TRACE_LEVEL];
+        let tensors = level.iter().any(|l| l == "TENSORS");
+        EffectiveSettings {
+            file: first(TRACE_FILE),
+            timestamps: tensors || level.iter().any(|l| l == "TIMESTAMPS"),
+            tensors,
+            rate: first(TRACE_RATE).parse().unwrap_or(1000),
+            count: first(TRACE_COUNT).parse().unwrap_or(-1),
+            log_frequency: first(LOG_FREQUENCY).parse().unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Sampler {
+    seen: u64,
+    traced: i64,
+}
+
+/// Records for one trace file. With a `log_frequency` of zero each trace is
+/// appended to the file as it finishes, keeping it a valid JSON array;
+/// otherwise every `log_frequency` traces are written to `<file>.<index>`.
+#[derive(Debug, Default)]
+struct TraceFile {
+    records: Vec<serde_json::Value>,
+    buffered: u32,
+    index: u32,
+    rolling: bool,
+}
+
+impl TraceFile {
+    fn roll(&mut self, fname: &str, writer: &Sender<Write>) {
+        let _ = writer.send(Write::Records(
+            format!("{}.{}", fname, self.index),
+            std::mem::take(&mut self.records),
+        ));
+        self.buffered = 0;
+        self.index += 1;
+    }
+}
+
+/// File writes handed to the writer thread, so trace files are never written
+/// while the trace settings are locked.
+#[derive(Debug)]
+enum Write {
+    Append(String, Vec<serde_json::Value>),
+    Records(String, Vec<serde_json::Value>),
+    Flushed(oneshot::Sender<()>),
+}
+
+/// Runs the writes sent to `writes` in order until the [`TraceManager`] is
+/// dropped.
+fn write_traces(writes: Receiver<Write>) {
+    let mut appending = HashMap::new();
+    for write in writes {
+        match write {
+            Write::Append(fname, records) => append(&mut appending, &fname, &records),
+            Write::Records(fname, records) => write_records(&fname, &records),
+            Write::Flushed(done) => {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Appends `records` to the array in `fname`, creating it on first use,
+/// by overwriting its closing bracket.
+fn append(
+    appending: &mut HashMap<String, std::fs::File>,
+    fname: &str,
+    records: &[serde_json::Value],
+) {
+    use std::io::{Seek, SeekFrom, Write};
+
+    let result = (|| -> std::io::Result<()> {
+        let first = !appending.contains_key(fname);
+        let file = match appending.entry(fname.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(std::fs::File::create(fname)?),
+        };
+        let mut buf = Vec::new();
+        for (index, record) in records.iter().enumerate() {
+            buf.push(if first && index == 0 { b'[' } else { b',' });
+            serde_json::to_writer(&mut buf, record)?;
+        }
+        buf.push(b']');
+        if !first {
+            file.seek(SeekFrom::End(-1))?;
+        }
+        file.write_all(&buf)
+    })();
+    if let Err(e) = result {
+        log::error!("trace: failed to write '{}': {}", fname, e);
+        appending.remove(fname);
+    }
+}
+
+#[derive(Debug)]
+pub struct TraceManager {
+    global: Settings,
+    models: HashMap<String, Settings>,
+    samplers: HashMap<String, Sampler>,
+    files: HashMap<String, TraceFile>,
+    writer: Sender<Write>,
+    next_id: u64,
+}
+
+impl Default for TraceManager {
+    fn default() -> Self {
+        let (writer, writes) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("trace-writer".to_string())
+            .spawn(move || write_traces(writes))
+            .expect("failed to spawn the trace writer");
+        TraceManager {
+            global: default_settings(),
+            models: HashMap::new(),
+            samplers: HashMap::new(),
+            files: HashMap::new(),
+            writer,
+            next_id: 1,
+        }
+    }
+}
+
+fn now_ns() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// A request being traced. Timestamps are collected as the request moves
+/// through the mock and written out by [`TraceManager::finish`].
+#[derive(Debug)]
+pub struct ActiveTrace {
+    id: u64,
+    model_name: String,
+    model_version: String,
+    file: String,
+    tensors: bool,
+    timestamps: Vec<serde_json::Value>,
+    activities: Vec<serde_json::Value>,
+}
+
+impl ActiveTrace {
+    pub fn stamp(&mut self, name: &str) {
+        self.timestamps.push(serde_json::json!({
+            "name": name,
+            "ns": now_ns() as u64,
+        }));
+    }
+
+    fn tensor(
+        &mut self,
+        activity: &str,
+        name: &str,
+        datatype: &str,
+        shape: &[i64],
+        contents: Option<&InferTensorContents>,
+        raw: Option<&[u8]>,
+    ) {
+        let shape = shape
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        self.activities.push(serde_json::json!({
+            "id": self.id,
+            "activity": activity,
+            "tensor": {
+                "name": name,
+                "data": tensor_data(datatype, contents, raw),
+                "shape": shape,
+                "dtype": datatype,
+            },
+        }));
+    }
+
+    pub fn inputs(&mut self, request: &server::ModelInferRequest) {
+        if !self.tensors {
+            return;
+        }
+        let mut raw = request.raw_input_contents.iter();
+        for input in &request.inputs {
+            let raw = match input.contents {
+                Some(_) => None,
+                None => raw.next().map(Vec::as_slice),
+            };
+            self.tensor(
+                "TENSOR_QUEUE_INPUT",
+                &input.name,
+                &input.datatype,
+                &input.shape,
+                input.contents.as_ref(),
+                raw,
+            );
+        }
+    }
+
+    pub fn outputs(&mut self, response: &server::ModelInferResponse) {
+        if !self.tensors {
+            return;
+        }
+        for (index, output) in response.outputs.iter().enumerate() {
+            self.tensor(
+                "TENSOR_BACKEND_OUTPUT",
+                &output.name,
+                &output.datatype,
+                &output.shape,
+                output.contents.as_ref(),
+                response.raw_output_contents.get(index).map(Vec::as_slice),
+            );
+        }
+    }
+}
+
+/// Renders tensor contents as the comma-separated list Triton writes in
+/// TENSORS traces.
+fn tensor_data(
+    datatype: &str,
+    contents: Option<&InferTensorContents>,
+    raw: Option<&[u8]>,
+) -> String {
+    fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+        values.map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+    }
+    fn le<const N: usize, T>(raw: &[u8], f: fn([u8; N]) -> T) -> std::vec::IntoIter<T> {
+        let values: Vec<T> = raw
+            .chunks_exact(N)
+            .map(|c| f(c.try_into().unwrap()))
+            .collect();
+        values.into_iter()
+    }
+    if let Some(contents) = contents {
+        return match datatype {
+            "BOOL" => join(contents.bool_contents.iter()),
+            "INT8" | "INT16" | "INT32" => join(contents.int_contents.iter()),
+            "INT64" => join(contents.int64_contents.iter()),
+            "UINT8" | "UINT16" | "UINT32" => join(contents.uint_contents.iter()),
+            "UINT64" => join(contents.uint64_contents.iter()),
+            "FP32" => join(contents.fp32_contents.iter()),
+            "FP64" => join(contents.fp64_contents.iter()),
+            "BYTES" => join(
+                contents
+                    .bytes_contents
+                    .iter()
+                    .map(|b| String::from_utf8_lossy(b).into_owned()),
+            ),
+            _ => String::new(),
+        };
+    }
+    let Some(raw) = raw else {
+        return String::new();
+    };
+    match datatype {
+        "BOOL" => join(raw.iter().map(|b| *b != 0)),
+        "INT8" => join(le(raw, i8::from_le_bytes)),
+        "INT16" => join(le(raw, i16::from_le_bytes)),
+        "INT32" => join(le(raw, i32::from_le_bytes)),
+        "INT64" => join(le(raw, i64::from_le_bytes)),
+        "UINT8" => join(raw.iter()),
+        "UINT16" => join(le(raw, u16::from_le_bytes)),
+        "UINT32" => join(le(raw, u32::from_le_bytes)),
+        "UINT64" => join(le(raw, u64::from_le_bytes)),
+        "FP32" => join(le(raw, f32::from_le_bytes)),
+        "FP64" => join(le(raw, f64::from_le_bytes)),
+        "BYTES" => {
+            let mut values = Vec::new();
+            let mut rest = raw;
+            while rest.len() >= 4 {
+                let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+                let end = (4 + len).min(rest.len());
+                values.push(String::from_utf8_lossy(&rest[4..end]).into_owned());
+                rest = &rest[end..];
+            }
+            values.join(",")
+        }
+        _ => String::new(),
+    }
+}
+
+impl TraceManager {
+    fn effective(&self, model_name: &str) -> Settings {
+        let mut settings = self.global.clone();
+        if let Some(overrides) = self.models.get(model_name) {
+            settings.extend(overrides.clone());
+        }
+        settings
+    }
+
+    fn response(&self, model_name: &str) -> TraceSettingResponse {
+        let settings = self
+            .effective(model_name)
+            .into_iter()
+            .map(|(key, value)| (key, trace_setting_response::SettingValue { value }))
+            .collect();
+        TraceSettingResponse { settings }
+    }
+
+    /// Applies a TraceSetting request. An empty model name updates the global
+    /// settings; for a model, a setting with no values clears the override so
+    /// the global value applies again.
+    pub fn update(
+        &mut self,
+        model_name: &str,
+        settings: &HashMap<String, trace_setting_request::SettingValue>,
+    ) -> Result<TraceSettingResponse, Status> {
+        for (key, setting) in settings {
+            if !setting.value.is_empty() || model_name.is_empty() {
+                validate(key, &setting.value)?;
+            }
+        }
+        let target = if model_name.is_empty() {
+            &mut self.global
+        } else {
+            self.models.entry(model_name.to_string()).or_default()
+        };
+        for (key, setting) in settings {
+            if setting.value.is_empty() {
+                if model_name.is_empty() {
+                    target.insert(key.clone(), default_settings()[key].clone());
+                } else {
+                    target.remove(key);
+                }
+            } else {
+                target.insert(key.clone(), setting.value.clone());
+            }
+        }
+        if !settings.is_empty() {
+            if model_name.is_empty() {
+                self.samplers.clear();
+            } else {
+                self.samplers.remove(model_name);
+            }
+        }
+        self.flush_all();
+        Ok(self.response(model_name))
+    }
+
+    /// Decides whether a request to `model_name` is sampled under the current
+    /// settings, returning the trace to fill in if it is.
+    pub fn start(&mut self, model_name: &str, model_version: &str) -> Option<ActiveTrace> {
+        let settings = EffectiveSettings::parse(&self.effective(model_name));
+        if !settings.timestamps || settings.rate == 0 {
+            return None;
+        }
+        let sampler = self.samplers.entry(model_name.to_string()).or_default();
+        let seen = sampler.seen;
+        sampler.seen += 1;
+        if !seen.is_multiple_of(u64::from(settings.rate)) {
+            return None;
+        }
+        if settings.count >= 0 && sampler.traced >= i64::from(settings.count) {
+            return None;
+        }
+        sampler.traced += 1;
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut trace = ActiveTrace {
+            id,
+            model_name: model_name.to_string(),
+            model_version: model_version.to_string(),
+            file: settings.file,
+            tensors: settings.tensors,
+            timestamps: Vec::new(),
+            activities: Vec::new(),
+        };
+        trace.stamp("REQUEST_START");
+        Some(trace)
+    }
+
+    pub fn finish(&mut self, mut trace: ActiveTrace) {
+        trace.stamp("REQUEST_END");
+        let version: i64 = trace.model_version.parse().unwrap_or(-1);
+        let mut records = vec![
+            serde_json::json!({
+                "id": trace.id,
+                "model_name": trace.model_name,
+                "model_version": version,
+            }),
+            serde_json::json!({
+                "id": trace.id,
+                "timestamps": trace.timestamps,
+            }),
+        ];
+        records.append(&mut trace.activities);
+        if trace.file.is_empty() {
+            for record in &records {
+                log::info!("trace: {}", record);
+            }
+            return;
+        }
+        let log_frequency =
+            EffectiveSettings::parse(&self.effective(&trace.model_name)).log_frequency;
+        let file = self.files.entry(trace.file.clone()).or_default();
+        file.rolling = log_frequency > 0;
+        if !file.rolling {
+            let _ = self.writer.send(Write::Append(trace.file, records));
+            return;
+        }
+        file.records.append(&mut records);
+        file.buffered += 1;
+        if file.buffered >= log_frequency {
+            file.roll(&trace.file, &self.writer);
+        }
+    }
+
+    /// Writes out traces still buffered for a `log_frequency` rollover.
+    pub fn flush_all(&mut self) {
+        for (fname, file) in &mut self.files {
+            if file.rolling && file.buffered > 0 {
+                file.roll(fname, &self.writer);
+            }
+        }
+    }
+
+    /// Resolves once every write queued so far has reached its file.
+    pub fn flushed(&self) -> oneshot::Receiver<()> {
+        let (done, flushed) = oneshot::channel();
+        let _ = self.writer.send(Write::Flushed(done));
+        flushed
+    }
+}
+
+fn write_records(fname: &str, records: &[serde_json::Value]) {
+    let result = std::fs::File::create(fname)
+        .map_err(|e| e.to_string())
+        .and_then(|fout| serde_json::to_writer(fout, records).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        log::error!("trace: failed to write '{}': {}", fname, e);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::{server::grpc_inference_service_client::GrpcInferenceServiceClient, CliOptions};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::sync::Mutex;
+
+use tonic::{transport::Channel, Status};
+
+pub type Client = GrpcInferenceServiceClient<Channel>;
+
+/// How long switching a model to record or proxy waits for the upstream.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where a model's requests are answered from: the loaded recording, the
+/// upstream Triton with its responses recorded, or the upstream Triton alone.
+#[derive(
+    clap::ValueEnum, serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Replay,
+    Record,
+    Proxy,
+}
+
+/// The connections to the upstream Triton and the mode each model is served
+/// in. Models without a mode of their own follow `default_mode`; both can be
+/// changed at runtime, connecting upstream on demand.
+#[derive(Debug, Default)]
+pub struct Upstream {
+    options: Option<Arc<CliOptions>>,
+    default_mode: Mode,
+    modes: HashMap<String, Mode>,
+    clients: HashMap<String, Client>,
+}
+
+impl Upstream {
+    pub fn new(options: Arc<CliOptions>) -> Self {
+        Upstream {
+            options: Some(options),
+            ..Default::default()
+        }
+    }
+
+    pub fn mode(&self, model: &str) -> Mode {
+        self.modes.get(model).copied().unwrap_or(self.default_mode)
+    }
+
+    pub fn default_mode(&self) -> Mode {
+        self.default_mode
+    }
+
+    /// The models given a mode of their own.
+    pub fn modes(&self) -> BTreeMap<String, Mode> {
+        self.modes
+            .iter()
+            .map(|(model, mode)| (model.clone(), *mode))
+            .collect()
+    }
+
+    /// The client to forward a model's requests to and whether its responses
+    /// are recorded, or `None` when the model is replayed.
+    pub fn route(&self, model: &str) -> Result<Option<(Client, bool)>, Status> {
+        let mode = self.mode(model);
+        if mode == Mode::Replay {
+            return Ok(None);
+        }
+        match self.clients.get(model) {
+            Some(client) => Ok(Some((client.clone(), mode == Mode::Record))),
+            None => Err(Status::unavailable(format!(
+                "no upstream connection for model '{}'",
+                model
+            ))),
+        }
+    }
+
+    /// Serves `model` (or every model without a mode of its own, when `None`)
+    /// in `mode`. Switching a single model to record or proxy fails if it
+    /// cannot be connected upstream; switching the default only warns about
+    /// models that cannot be. The lock is not held while connecting, so
+    /// requests keep being answered meanwhile.
+    pub async fn set_mode(
+        upstream: &Mutex<Upstream>,
+        model: Option<&str>,
+        mode: Mode,
+    ) -> Result<(), Status> {
+        let (options, unconnected) = {
+            let upstream = upstream.lock().await;
+            let unconnected: Vec<&str> = match model {
+                _ if mode == Mode::Replay => Vec::new(),
+                Some(model) => vec![model],
+                None => crate::CLIENT_PORTS
+                    .iter()
+                    .flat_map(|(models, _)| models.iter().copied())
+                    .collect(),
+            };
+            let unconnected: Vec<&str> = unconnected
+                .into_iter()
+                .filter(|model| !upstream.clients.contains_key(*model))
+                .collect();
+            (upstream.options.clone(), unconnected)
+        };
+        let mut clients = Vec::new();
+        for name in unconnected {
+            match connect(options.as_deref(), name).await {
+                Ok(client) => clients.push((name, client)),
+                Err(e) if model.is_some() => return Err(Status::unavailable(e.to_string())),
+                Err(e) => log::warn!("{}", e),
+            }
+        }
+        let mut upstream = upstream.lock().await;
+        for (name, client) in clients {
+            upstream.clients.entry(name.to_string()).or_insert(client);
+        }
+        match model {
+            Some(model) => {
+                upstream.modes.insert(model.to_string(), mode);
+            }
+            None => upstream.default_mode = mode,
+        }
+        log::info!("mode of {}: {:?}", model.unwrap_or("all models"), mode);
+        Ok(())
+    }
+}
+
+/// Connects to the upstream Triton serving `model`, giving up after
+/// `CONNECT_TIMEOUT`.
+async fn connect(options: Option<&CliOptions>, model: &str) -> Result<Client, Box<dyn Error>> {
+    let options = options.ok_or("no upstream Triton is configured")?;
+    let port = crate::CLIENT_PORTS
+        .iter()
+        .find(|(models, _)| models.contains(&model))
+        .map(|(_, port)| *port)
+        .ok_or_else(|| format!("no upstream port for model '{}'", model))?;
+    let remote_tls = options.remote_tls()?;
+    let scheme = if remote_tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let address = format!("{}://{}:{}", scheme, options.remote_host, port);
+    log::info!("Connecting to remote gRPC endpoint: {address}");
+    let mut endpoint = Channel::from_shared(address.clone())?.connect_timeout(CONNECT_TIMEOUT);
+    if let Some(tls) = remote_tls {
+        endpoint = endpoint.tls_config(tls)?;
+    }
+    let channel = endpoint
+        .connect()
+        .await
+        .map_err(|_| format!("Failed to connect to remote gRPC endpoint: {address}"))?;
+    Ok(options.configure_client(GrpcInferenceServiceClient::new(channel)))
+}
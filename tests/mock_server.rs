@@ -0,0 +1,1602 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde_json::{json, Value};
+
+use triton_mock::{
+    server::{
+        self,
+        grpc_inference_service_client::GrpcInferenceServiceClient,
+        infer_parameter,
+        model_infer_request::{InferInputTensor, InferRequestedOutputTensor},
+        InferParameter, ModelConfig, ModelConfigRequest, ModelInferRequest,
+    },
+    tensor, CassetteBuilder, CliOptions, Fill, ListenAddress, MockServer, MockServerBuilder, Mode,
+    Tensor,
+};
+
+type Client = GrpcInferenceServiceClient<tonic::transport::Channel>;
+
+async fn client(mock: &MockServer) -> Client {
+    GrpcInferenceServiceClient::connect(format!("http://{}", mock.grpc_address()))
+        .await
+        .unwrap()
+}
+
+/// Sends an HTTP request to `address`, returning the status, headers and body
+/// of the response.
+async fn http(
+    address: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Vec<u8>,
+) -> (u16, hyper::HeaderMap, Vec<u8>) {
+    let mut request = hyper::Request::builder()
+        .method(method)
+        .uri(format!("http://{}{}", address, path));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = hyper::Client::new()
+        .request(request.body(hyper::Body::from(body)).unwrap())
+        .await
+        .unwrap();
+    let status = response.status().as_u16();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, body.to_vec())
+}
+
+/// Calls the mock's admin API with a JSON body, returning the status and the
+/// JSON response, or `Value::Null` for an empty one.
+async fn admin(mock: &MockServer, method: &str, path: &str, body: Value) -> (u16, Value) {
+    let body = if body.is_null() {
+        Vec::new()
+    } else {
+        serde_json::to_vec(&body).unwrap()
+    };
+    let (status, _, body) = http(
+        mock.admin_address().unwrap(),
+        method,
+        path,
+        &[("content-type", "application/json")],
+        body,
+    )
+    .await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+fn recording(score: f32) -> Vec<u8> {
+    CassetteBuilder::new()
+        .config(
+            "",
+            ModelConfig {
+                name: "ner".into(),
+                ..Default::default()
+            },
+        )
+        .infer(
+            "ner",
+            "",
+            Vec::new(),
+            vec![Tensor::new("scores", "FP32", [1], [score]).unwrap()],
+        )
+        .to_bytes()
+}
+
+async fn infer_score(mock: &MockServer) -> f32 {
+    let response = client(mock)
+        .await
+        .model_infer(ModelInferRequest {
+            model_name: "ner".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    f32::from_le_bytes(response.raw_output_contents[0][..].try_into().unwrap())
+}
+
+#[tokio::test]
+async fn mocks_are_isolated() {
+    let first = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .start()
+        .await
+        .unwrap();
+    let second = MockServer::builder()
+        .recording_bytes(recording(0.75))
+        .start()
+        .await
+        .unwrap();
+    assert_ne!(first.grpc_address(), second.grpc_address());
+
+    assert_eq!(infer_score(&first).await, 0.25);
+    assert_eq!(infer_score(&second).await, 0.75);
+
+    first.shutdown().await.unwrap();
+    second.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn recording_bytes_cannot_be_recorded_into() {
+    let started = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .mode(Mode::Record)
+        .start()
+        .await;
+    assert!(started.is_err());
+}
+
+#[tokio::test]
+async fn built_cassettes_round_trip() {
+    // Any model in the recording is served, not only the ones the mock was
+    // built for.
+    let cassette = CassetteBuilder::new()
+        .config(
+            "",
+            ModelConfig {
+                name: "reranker".into(),
+                max_batch_size: 8,
+                ..Default::default()
+            },
+        )
+        .infer(
+            "reranker",
+            "",
+            vec![Tensor::new("query", "BYTES", [1], ["mock"]).unwrap()],
+            vec![Tensor::from_values("scores", [2], &[0.25f32, 0.75]).unwrap()],
+        )
+        .infer(
+            "reranker",
+            "",
+            Vec::new(),
+            vec![
+                Tensor::from_values("scores", [1], &[0.5f32]).unwrap(),
+                Tensor::from_values("labels", [1], &["PER".to_string()]).unwrap(),
+            ],
+        );
+    let mock = MockServer::builder()
+        .recording_bytes(cassette.to_bytes())
+        .start()
+        .await
+        .unwrap();
+    let mut client = client(&mock).await;
+
+    let config = client
+        .model_config(ModelConfigRequest {
+            name: "reranker".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .config
+        .unwrap();
+    assert_eq!(config.max_batch_size, 8);
+    let ready = client
+        .model_ready(server::ModelReadyRequest {
+            name: "reranker".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(ready.ready);
+
+    let infer = || {
+        let mut client = client.clone();
+        async move {
+            client
+                .model_infer(ModelInferRequest {
+                    model_name: "reranker".into(),
+                    ..Default::default()
+                })
+                .await
+        }
+    };
+    let first = infer().await.unwrap().into_inner();
+    assert_eq!(
+        tensor::response_output::<f32>(&first, "scores").unwrap(),
+        (vec![2], vec![0.25, 0.75])
+    );
+    let second = infer().await.unwrap().into_inner();
+    assert_eq!(
+        tensor::response_output::<f32>(&second, "scores").unwrap(),
+        (vec![1], vec![0.5])
+    );
+    assert_eq!(
+        tensor::response_output::<String>(&second, "labels").unwrap(),
+        (vec![1], vec!["PER".to_string()])
+    );
+    assert!(infer().await.is_err());
+
+    mock.shutdown().await.unwrap();
+}
+
+/// The `shared_memory_*` parameters placing a tensor in `region`.
+fn shm_parameters(region: &str, offset: i64, byte_size: i64) -> HashMap<String, InferParameter> {
+    let param = |choice| InferParameter {
+        parameter_choice: Some(choice),
+    };
+    HashMap::from([
+        (
+            "shared_memory_region".to_string(),
+            param(infer_parameter::ParameterChoice::StringParam(
+                region.to_string(),
+            )),
+        ),
+        (
+            "shared_memory_offset".to_string(),
+            param(infer_parameter::ParameterChoice::Int64Param(offset)),
+        ),
+        (
+            "shared_memory_byte_size".to_string(),
+            param(infer_parameter::ParameterChoice::Int64Param(byte_size)),
+        ),
+    ])
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn shared_memory_round_trip() {
+    let key = format!("triton-mock-test-{}", std::process::id());
+    let path = format!("/dev/shm/{}", key);
+    let mut contents = vec![0u8; 64];
+    contents[..4].copy_from_slice(&0.5f32.to_le_bytes());
+    std::fs::write(&path, &contents).unwrap();
+    let mock = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .start()
+        .await
+        .unwrap();
+    let mut client = client(&mock).await;
+
+    // Regions must fit in their file, and keys cannot leave /dev/shm.
+    for (key, byte_size) in [(key.as_str(), 65), ("../etc/passwd", 4), ("a/b", 4)] {
+        let status = client
+            .system_shared_memory_register(server::SystemSharedMemoryRegisterRequest {
+                name: "invalid".into(),
+                key: key.into(),
+                offset: 0,
+                byte_size,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument, "{}", key);
+    }
+    client
+        .system_shared_memory_register(server::SystemSharedMemoryRegisterRequest {
+            name: "region".into(),
+            key: key.clone(),
+            offset: 0,
+            byte_size: 64,
+        })
+        .await
+        .unwrap();
+
+    let response = client
+        .model_infer(ModelInferRequest {
+            model_name: "ner".into(),
+            inputs: vec![InferInputTensor {
+                name: "text".into(),
+                datatype: "FP32".into(),
+                shape: vec![1],
+                parameters: shm_parameters("region", 0, 4),
+                ..Default::default()
+            }],
+            outputs: vec![InferRequestedOutputTensor {
+                name: "scores".into(),
+                parameters: shm_parameters("region", 32, 4),
+            }],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.raw_output_contents[0].is_empty());
+    let contents = std::fs::read(&path).unwrap();
+    assert_eq!(contents[32..36], 0.25f32.to_le_bytes());
+
+    // The input was read from the region before the request was journaled.
+    let (_, journal) = admin(&mock, "GET", "/admin/requests?model=ner", Value::Null).await;
+    assert_eq!(
+        journal["requests"][0]["request"]["inputs"][0]["data"],
+        json!([0.5])
+    );
+
+    mock.shutdown().await.unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// The value of the metric in the line starting with `series` on the mock's
+/// metrics endpoint, if any.
+async fn metric(mock: &MockServer, series: &str) -> Option<u64> {
+    let (_, _, body) = http(mock.metrics_address(), "GET", "/metrics", &[], Vec::new()).await;
+    String::from_utf8(body)
+        .unwrap()
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.trim().parse().ok())
+}
+
+#[tokio::test]
+async fn metrics_count_requests_and_replay() {
+    let mock = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .start()
+        .await
+        .unwrap();
+    let mut client = client(&mock).await;
+    let infer = ModelInferRequest {
+        model_name: "ner".into(),
+        ..Default::default()
+    };
+    client.model_infer(infer.clone()).await.unwrap();
+    client.model_infer(infer).await.unwrap_err();
+    for (version, answered) in [("", true), ("bogus", false)] {
+        let config = client
+            .model_config(ModelConfigRequest {
+                name: "ner".into(),
+                version: version.into(),
+            })
+            .await;
+        assert_eq!(config.is_ok(), answered);
+    }
+
+    let labels = r#"{model="ner",version=""}"#;
+    let series = |name: &str| format!("{}{}", name, labels);
+    assert_eq!(
+        metric(&mock, &series("nv_inference_request_success")).await,
+        Some(1)
+    );
+    assert_eq!(
+        metric(&mock, &series("nv_inference_request_failure")).await,
+        Some(1)
+    );
+    assert_eq!(
+        metric(&mock, &series("nv_inference_pending_request_count")).await,
+        Some(0)
+    );
+    let replay =
+        |name: &str, rpc: &str| format!(r#"{}{{model="ner",version="",rpc="{}"}}"#, name, rpc);
+    assert_eq!(
+        metric(&mock, &replay("triton_mock_replay_hits", "model_infer")).await,
+        Some(1)
+    );
+    assert_eq!(
+        metric(&mock, &replay("triton_mock_replay_misses", "model_infer")).await,
+        Some(1)
+    );
+    // Unversioned recordings ignore the requested version, so both configs
+    // count under the empty one, the second as a miss.
+    assert_eq!(
+        metric(&mock, &replay("triton_mock_replay_hits", "model_config")).await,
+        Some(1)
+    );
+    assert_eq!(
+        metric(&mock, &replay("triton_mock_replay_misses", "model_config")).await,
+        Some(1)
+    );
+    let (_, _, body) = http(mock.metrics_address(), "GET", "/metrics", &[], Vec::new()).await;
+    assert!(!String::from_utf8(body).unwrap().contains("bogus"));
+    mock.shutdown().await.unwrap();
+
+    // A synthesized response is not a replay miss.
+    let mock = MockServer::builder()
+        .recording_bytes(synthetic_recording())
+        .synthetic(Fill::Zeros)
+        .start()
+        .await
+        .unwrap();
+    self::client(&mock)
+        .await
+        .model_infer(ModelInferRequest {
+            model_name: "ner".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        metric(&mock, &series("nv_inference_request_success")).await,
+        Some(1)
+    );
+    assert_eq!(
+        metric(&mock, &replay("triton_mock_replay_misses", "model_infer")).await,
+        None
+    );
+    mock.shutdown().await.unwrap();
+}
+
+/// A recording of a config with two outputs and no responses.
+fn synthetic_recording() -> Vec<u8> {
+    let output = |name: &str, data_type: server::DataType| server::ModelOutput {
+        name: name.into(),
+        data_type: data_type as i32,
+        dims: vec![2],
+        ..Default::default()
+    };
+    CassetteBuilder::new()
+        .config(
+            "",
+            ModelConfig {
+                name: "ner".into(),
+                max_batch_size: 4,
+                output: vec![
+                    output("scores", server::DataType::TypeFp32),
+                    output("labels", server::DataType::TypeInt64),
+                ],
+                ..Default::default()
+            },
+        )
+        .to_bytes()
+}
+
+/// The journaled requests matching `query`.
+async fn journal(mock: &MockServer, query: &str) -> Value {
+    let (status, body) = admin(
+        mock,
+        "GET",
+        &format!("/admin/requests?{}", query),
+        Value::Null,
+    )
+    .await;
+    assert_eq!(status, 200);
+    body
+}
+
+#[tokio::test]
+async fn journal_filters_requests() {
+    let mock = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .journal_size(4)
+        .start()
+        .await
+        .unwrap();
+    let mut client = client(&mock).await;
+    for (batch_size, team) in [(8, "search"), (4, "search"), (8, "ads")] {
+        let mut request = tonic::Request::new(ModelInferRequest {
+            model_name: "cross_encoder".into(),
+            inputs: vec![InferInputTensor {
+                name: "input_ids".into(),
+                datatype: "INT64".into(),
+                shape: vec![batch_size, 16],
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        request
+            .metadata_mut()
+            .insert("x-team", team.parse().unwrap());
+        let _ = client.model_infer(request).await;
+    }
+    // The request's own example: exactly two requests to `cross_encoder`
+    // with batch size 8.
+    let body = journal(&mock, "model=cross_encoder&batch_size=8").await;
+    assert_eq!(body["count"], 2);
+    let body = journal(&mock, "model=cross_encoder&header=x-team:search").await;
+    assert_eq!(body["count"], 2);
+    let first = body["requests"][0]["seq"].as_u64().unwrap();
+    let body = journal(&mock, &format!("header=x-team:search&after={}", first)).await;
+    assert_eq!(body["count"], 1);
+    assert_eq!(
+        body["requests"][0]["request"]["inputs"][0]["shape"],
+        json!([4, 16])
+    );
+
+    // Readiness over HTTP is journaled with the peer it came from, and the
+    // journal keeps only the most recent four requests.
+    let (status, _, _) = http(
+        mock.http_address(),
+        "GET",
+        "/v2/models/ner/ready",
+        &[],
+        Vec::new(),
+    )
+    .await;
+    assert_eq!(status, 200);
+    client
+        .model_infer(ModelInferRequest {
+            model_name: "ner".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    let body = journal(&mock, "").await;
+    assert_eq!(body["count"], 4);
+    let rpcs: Vec<&str> = body["requests"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["rpc"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        rpcs,
+        ["model_infer", "model_infer", "model_ready", "model_infer"]
+    );
+    let ready = &body["requests"][2];
+    assert_eq!(ready["outcome"]["source"], "config");
+    assert!(ready["peer"].as_str().unwrap().starts_with("127.0.0.1:"));
+    let body = journal(&mock, "model=cross_encoder&batch_size=8").await;
+    assert_eq!(body["count"], 1);
+    mock.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn models_outside_the_built_in_list() {
+    let stub = |model: &str| {
+        json!({
+            "rpc": "model_infer",
+            "model": model,
+            "response": {"outputs": [
+                {"name": "scores", "datatype": "FP32", "shape": [1], "data": [0.5]}
+            ]},
+        })
+    };
+    let mock = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .model("reranker")
+        .start()
+        .await
+        .unwrap();
+    let (status, _) = admin(&mock, "POST", "/admin/stubs", stub("reranker")).await;
+    assert_eq!(status, 200);
+    let (status, body) = admin(&mock, "POST", "/admin/stubs", stub("summarizer")).await;
+    assert_eq!(status, 400);
+    assert_eq!(body["error"], "unknown model 'summarizer'");
+    let (status, _) = admin(
+        &mock,
+        "PUT",
+        "/admin/mode",
+        json!({"model": "summarizer", "mode": "replay"}),
+    )
+    .await;
+    assert_eq!(status, 404);
+
+    let response = client(&mock)
+        .await
+        .model_infer(ModelInferRequest {
+            model_name: "reranker".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        tensor::response_output::<f32>(&response, "scores").unwrap(),
+        (vec![1], vec![0.5])
+    );
+    let status = client(&mock)
+        .await
+        .model_infer(ModelInferRequest {
+            model_name: "summarizer".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    mock.shutdown().await.unwrap();
+}
+
+/// Asks a mock synthesizing responses from `synthetic_recording` for a batch
+/// of `batch` and the named outputs, or all of them.
+async fn synthesize(
+    mock: &MockServer,
+    batch: i64,
+    outputs: &[&str],
+) -> Result<server::ModelInferResponse, tonic::Status> {
+    client(mock)
+        .await
+        .model_infer(ModelInferRequest {
+            model_name: "ner".into(),
+            inputs: vec![InferInputTensor {
+                name: "text".into(),
+                datatype: "BYTES".into(),
+                shape: vec![batch],
+                ..Default::default()
+            }],
+            outputs: outputs
+                .iter()
+                .map(|name| InferRequestedOutputTensor {
+                    name: name.to_string(),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        })
+        .await
+        .map(tonic::Response::into_inner)
+}
+
+#[tokio::test]
+async fn synthetic_responses() {
+    let start = |fill: Fill| {
+        MockServer::builder()
+            .recording_bytes(synthetic_recording())
+            .synthetic(fill)
+            .start()
+    };
+
+    // Random fills depend only on the seed, across calls and mocks.
+    let first = start("random:7".parse().unwrap()).await.unwrap();
+    let second = start(Fill::Random(7)).await.unwrap();
+    let other_seed = start(Fill::Random(8)).await.unwrap();
+    let scores = |response: server::ModelInferResponse| {
+        tensor::response_output::<f32>(&response, "scores").unwrap()
+    };
+    let random = scores(synthesize(&first, 3, &[]).await.unwrap());
+    assert_eq!(random.0, vec![3, 2]);
+    assert!(random.1.iter().all(|v| (0.0..1.0).contains(v)));
+    assert_eq!(scores(synthesize(&first, 3, &[]).await.unwrap()), random);
+    assert_eq!(scores(synthesize(&second, 3, &[]).await.unwrap()), random);
+    assert_ne!(
+        scores(synthesize(&other_seed, 3, &[]).await.unwrap()),
+        random
+    );
+    for mock in [first, second, other_seed] {
+        mock.shutdown().await.unwrap();
+    }
+
+    assert_eq!("constant:2.5".parse(), Ok(Fill::Constant(2.5)));
+    assert!("constant:".parse::<Fill>().is_err());
+    assert!("constant".parse::<Fill>().is_err());
+    let mock = start("constant:2.5".parse().unwrap()).await.unwrap();
+    let response = synthesize(&mock, 1, &[]).await.unwrap();
+    assert_eq!(scores(response.clone()), (vec![1, 2], vec![2.5, 2.5]));
+    assert_eq!(
+        tensor::response_output::<i64>(&response, "labels").unwrap(),
+        (vec![1, 2], vec![2, 2])
+    );
+
+    // A request naming outputs gets only those.
+    let response = synthesize(&mock, 2, &["labels"]).await.unwrap();
+    assert_eq!(response.outputs.len(), 1);
+    assert_eq!(
+        tensor::response_output::<i64>(&response, "labels").unwrap(),
+        (vec![2, 2], vec![2; 4])
+    );
+
+    // Batches beyond the config's max_batch_size of 4 are rejected.
+    assert!(synthesize(&mock, 4, &[]).await.is_ok());
+    let status = synthesize(&mock, 5, &[]).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    mock.shutdown().await.unwrap();
+}
+
+/// Writes a CA and the `localhost` server and client certificates it signs
+/// as PEM files to a directory of their own, returning the directory.
+fn pki(name: &str) -> PathBuf {
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    let dir = std::env::temp_dir().join(format!("triton-mock-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    std::fs::write(dir.join("ca.pem"), ca.pem()).unwrap();
+    for leaf in ["server", "client"] {
+        let key = KeyPair::generate().unwrap();
+        let cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        std::fs::write(dir.join(format!("{}.pem", leaf)), cert.pem()).unwrap();
+        std::fs::write(dir.join(format!("{}.key", leaf)), key.serialize_pem()).unwrap();
+    }
+    dir
+}
+
+/// A builder from the binary's command line `args`, with every listener on
+/// an ephemeral port.
+fn builder_with(args: &[&str]) -> MockServerBuilder {
+    use clap::Parser;
+
+    let ephemeral = || "127.0.0.1:0".parse::<ListenAddress>().unwrap();
+    let options =
+        CliOptions::parse_from(std::iter::once("triton-mock").chain(args.iter().copied()));
+    MockServerBuilder::from_options(options)
+        .grpc_listen(ephemeral())
+        .http_listen(ephemeral())
+        .metrics_listen(ephemeral())
+}
+
+/// Connects to `address` over TLS, trusting the CA in `pki` and presenting
+/// its client certificate if `identity` is set.
+async fn tls_client(
+    address: &str,
+    pki: &std::path::Path,
+    identity: bool,
+) -> Result<Client, tonic::transport::Error> {
+    use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+    let read = |file: &str| std::fs::read(pki.join(file)).unwrap();
+    let mut tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(read("ca.pem")))
+        .domain_name("localhost");
+    if identity {
+        tls = tls.identity(Identity::from_pem(read("client.pem"), read("client.key")));
+    }
+    let channel = Channel::from_shared(format!("https://{}", address))
+        .unwrap()
+        .tls_config(tls)?
+        .connect()
+        .await?;
+    Ok(GrpcInferenceServiceClient::new(channel))
+}
+
+fn ner_request() -> ModelInferRequest {
+    ModelInferRequest {
+        model_name: "ner".into(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn tls_listener() {
+    let pki = pki("tls");
+    let path = |file: &str| pki.join(file).to_string_lossy().into_owned();
+    let mock = builder_with(&[
+        "--grpc-use-ssl",
+        "--grpc-server-cert",
+        &path("server.pem"),
+        "--grpc-server-key",
+        &path("server.key"),
+    ])
+    .recording_bytes(recording(0.25))
+    .start()
+    .await
+    .unwrap();
+
+    let mut client = tls_client(mock.grpc_address(), &pki, false).await.unwrap();
+    let response = client
+        .model_infer(ner_request())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(
+        tensor::response_output::<f32>(&response, "scores").unwrap(),
+        (vec![1], vec![0.25])
+    );
+    // Plaintext clients are not served.
+    let plaintext =
+        GrpcInferenceServiceClient::connect(format!("http://{}", mock.grpc_address())).await;
+    if let Ok(mut plaintext) = plaintext {
+        assert!(plaintext.model_infer(ner_request()).await.is_err());
+    }
+
+    mock.shutdown().await.unwrap();
+    std::fs::remove_dir_all(pki).unwrap();
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_a_client_certificate() {
+    let pki = pki("mtls");
+    let path = |file: &str| pki.join(file).to_string_lossy().into_owned();
+    let mock = builder_with(&[
+        "--grpc-use-ssl-mutual",
+        "--grpc-server-cert",
+        &path("server.pem"),
+        "--grpc-server-key",
+        &path("server.key"),
+        "--grpc-root-cert",
+        &path("ca.pem"),
+    ])
+    .recording_bytes(recording(0.25))
+    .start()
+    .await
+    .unwrap();
+
+    // The handshake may complete before the server rejects the missing
+    // certificate, so the rejection can surface on connect or on the call.
+    if let Ok(mut client) = tls_client(mock.grpc_address(), &pki, false).await {
+        assert!(client.model_infer(ner_request()).await.is_err());
+    }
+    let mut client = tls_client(mock.grpc_address(), &pki, true).await.unwrap();
+    client.model_infer(ner_request()).await.unwrap();
+
+    mock.shutdown().await.unwrap();
+    std::fs::remove_dir_all(pki).unwrap();
+}
+
+#[tokio::test]
+async fn record_mode_connects_to_a_tls_upstream() {
+    let pki = pki("upstream-tls");
+    let path = |file: &str| pki.join(file).to_string_lossy().into_owned();
+    // llama_7b is recorded from the upstream Triton's port 8305.
+    let upstream = builder_with(&[
+        "--grpc-use-ssl",
+        "--grpc-server-cert",
+        &path("server.pem"),
+        "--grpc-server-key",
+        &path("server.key"),
+    ])
+    .grpc_listen("127.0.0.1:8305".parse().unwrap())
+    .recording_bytes(
+        CassetteBuilder::new()
+            .infer(
+                "llama_7b",
+                "",
+                Vec::new(),
+                vec![Tensor::new("text_output", "BYTES", [1], ["from upstream"]).unwrap()],
+            )
+            .to_bytes(),
+    )
+    .start()
+    .await
+    .unwrap();
+
+    let recording = path("recorded.json.gz");
+    let mock = builder_with(&[
+        "--remote-host",
+        "localhost",
+        "--remote-ca-cert",
+        &path("ca.pem"),
+    ])
+    .recording_path(recording.as_str())
+    .mode(Mode::Record)
+    .start()
+    .await
+    .unwrap();
+    let request = ModelInferRequest {
+        model_name: "llama_7b".into(),
+        ..Default::default()
+    };
+    let text = |response: server::ModelInferResponse| {
+        tensor::response_output::<String>(&response, "text_output").unwrap()
+    };
+    let response = client(&mock)
+        .await
+        .model_infer(request.clone())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(text(response), (vec![1], vec!["from upstream".to_string()]));
+    mock.shutdown().await.unwrap();
+    upstream.shutdown().await.unwrap();
+
+    // What was recorded over TLS replays without the upstream.
+    let replay = MockServer::builder()
+        .recording_path(recording.as_str())
+        .start()
+        .await
+        .unwrap();
+    let response = client(&replay)
+        .await
+        .model_infer(request)
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(text(response), (vec![1], vec!["from upstream".to_string()]));
+    replay.shutdown().await.unwrap();
+    std::fs::remove_dir_all(pki).unwrap();
+}
+
+/// Registers a stub through the admin API, returning its id.
+async fn add_stub(mock: &MockServer, stub: Value) -> u64 {
+    let (status, body) = admin(mock, "POST", "/admin/stubs", stub).await;
+    assert_eq!(status, 200, "{}", body);
+    body["id"].as_u64().unwrap()
+}
+
+/// A `model_infer` stub for ner answering with `score`, extended by `spec`.
+fn score_stub(score: f32, spec: Value) -> Value {
+    let mut stub = json!({
+        "rpc": "model_infer",
+        "model": "ner",
+        "response": {"outputs": [
+            {"name": "scores", "datatype": "FP32", "shape": [1], "data": [score]}
+        ]},
+    });
+    stub.as_object_mut()
+        .unwrap()
+        .extend(spec.as_object().unwrap().clone());
+    stub
+}
+
+/// A ner request carrying `text` and, if given, the `mode` parameter.
+fn text_request(text: &str, mode: Option<&str>) -> ModelInferRequest {
+    ModelInferRequest {
+        model_name: "ner".into(),
+        inputs: vec![InferInputTensor {
+            name: "text".into(),
+            datatype: "BYTES".into(),
+            shape: vec![1],
+            contents: Some(server::InferTensorContents {
+                bytes_contents: vec![text.as_bytes().to_vec()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }],
+        parameters: mode
+            .map(|mode| {
+                let parameter = InferParameter {
+                    parameter_choice: Some(infer_parameter::ParameterChoice::StringParam(
+                        mode.into(),
+                    )),
+                };
+                HashMap::from([("mode".to_string(), parameter)])
+            })
+            .into_iter()
+            .flatten()
+            .collect(),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn stubs_answer_before_the_recording() {
+    let mock = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .start()
+        .await
+        .unwrap();
+    let client = client(&mock).await;
+    let score = |request: ModelInferRequest| {
+        let mut client = client.clone();
+        async move {
+            let response = client.model_infer(request).await?.into_inner();
+            Ok::<_, tonic::Status>(
+                tensor::response_output::<f32>(&response, "scores")
+                    .unwrap()
+                    .1[0],
+            )
+        }
+    };
+
+    // Stubs match on input data and request parameters; other requests
+    // fall through to the recording.
+    add_stub(
+        &mock,
+        score_stub(1.0, json!({"inputs": {"text": ["Paris"]}})),
+    )
+    .await;
+    add_stub(
+        &mock,
+        score_stub(2.0, json!({"parameters": {"mode": "fast"}})),
+    )
+    .await;
+    assert_eq!(score(text_request("Paris", None)).await.unwrap(), 1.0);
+    assert_eq!(
+        score(text_request("Rome", Some("fast"))).await.unwrap(),
+        2.0
+    );
+    assert_eq!(
+        score(text_request("Rome", Some("slow"))).await.unwrap(),
+        0.25
+    );
+    let (status, _) = admin(&mock, "DELETE", "/admin/stubs", Value::Null).await;
+    assert_eq!(status, 200);
+
+    // The lowest priority wins, then the most recently registered stub; a
+    // stub stops matching once it has answered `times` requests.
+    add_stub(&mock, score_stub(3.0, json!({}))).await;
+    add_stub(&mock, score_stub(4.0, json!({}))).await;
+    let limited = add_stub(&mock, score_stub(5.0, json!({"priority": 1, "times": 2}))).await;
+    let fallback = add_stub(&mock, score_stub(6.0, json!({"priority": 9}))).await;
+    for expected in [5.0, 5.0, 4.0, 4.0] {
+        assert_eq!(score(text_request("Paris", None)).await.unwrap(), expected);
+    }
+    let (_, stubs) = admin(&mock, "GET", "/admin/stubs", Value::Null).await;
+    let hits = |id: u64| {
+        stubs
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|stub| stub["id"] == id)
+            .unwrap()["hits"]
+            .clone()
+    };
+    assert_eq!(hits(limited), 2);
+    assert_eq!(hits(fallback), 0);
+
+    // Errors are returned after the delay.
+    add_stub(
+        &mock,
+        json!({
+            "rpc": "model_infer",
+            "model": "ner",
+            "priority": 0,
+            "error": {"code": "UNAVAILABLE", "message": "overloaded"},
+            "delay_ms": 200,
+        }),
+    )
+    .await;
+    let started = std::time::Instant::now();
+    let status = score(text_request("Paris", None)).await.unwrap_err();
+    assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    assert_eq!(status.message(), "overloaded");
+    mock.shutdown().await.unwrap();
+}
+
+/// The current state of the scenario `name`.
+async fn scenario_state(mock: &MockServer, name: &str) -> Value {
+    let (_, scenarios) = admin(mock, "GET", "/admin/scenarios", Value::Null).await;
+    scenarios
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|scenario| scenario["name"] == name)
+        .unwrap()["state"]
+        .clone()
+}
+
+#[tokio::test]
+async fn scenarios_gate_replay() {
+    let ingestor = |text: &str| vec![Tensor::new("text_output", "BYTES", [1], [text]).unwrap()];
+    let mock = MockServer::builder()
+        .recording_bytes(
+            CassetteBuilder::new()
+                .infer("ingestor", "", Vec::new(), ingestor("first"))
+                .infer("ingestor", "", Vec::new(), ingestor("second"))
+                .infer(
+                    "ner",
+                    "",
+                    Vec::new(),
+                    vec![Tensor::new("scores", "FP32", [1], [0.25]).unwrap()],
+                )
+                .infer(
+                    "ner",
+                    "",
+                    Vec::new(),
+                    vec![Tensor::new("scores", "FP32", [1], [0.75]).unwrap()],
+                )
+                .to_bytes(),
+        )
+        .start()
+        .await
+        .unwrap();
+    let client = client(&mock).await;
+    let infer = |model: &str| {
+        let mut client = client.clone();
+        let request = ModelInferRequest {
+            model_name: model.into(),
+            ..Default::default()
+        };
+        async move {
+            client
+                .model_infer(request)
+                .await
+                .map(tonic::Response::into_inner)
+        }
+    };
+    let ready = |model: &str| {
+        let mut client = client.clone();
+        let request = server::ModelReadyRequest {
+            name: model.into(),
+            ..Default::default()
+        };
+        async move {
+            client
+                .model_ready(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .ready
+        }
+    };
+
+    // The README's example: the first attempt fails, the retry is answered
+    // from the recording, and then the model is gone.
+    let (status, _) = admin(
+        &mock,
+        "POST",
+        "/admin/scenarios",
+        json!({
+            "name": "ingestor-retry",
+            "steps": [
+                {"state": "Started", "rpc": "model_infer", "model": "ingestor", "error": {"code": "UNAVAILABLE"}, "next_state": "retried"},
+                {"state": "retried", "rpc": "model_infer", "model": "ingestor", "next_state": "unloaded"},
+                {"state": "unloaded", "rpc": "model_infer", "model": "ingestor", "error": {"code": "NOT_FOUND", "message": "model unloaded"}},
+                {"state": "unloaded", "rpc": "model_ready", "model": "ingestor", "ready": false}
+            ]
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(ready("ingestor").await);
+    let status = infer("ingestor").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    // A failing step moves the scenario at once.
+    assert_eq!(scenario_state(&mock, "ingestor-retry").await, "retried");
+    let response = infer("ingestor").await.unwrap();
+    assert_eq!(
+        tensor::response_output::<String>(&response, "text_output")
+            .unwrap()
+            .1,
+        ["first"]
+    );
+    assert_eq!(scenario_state(&mock, "ingestor-retry").await, "unloaded");
+    let status = infer("ingestor").await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    assert_eq!(status.message(), "model unloaded");
+    assert!(!ready("ingestor").await);
+    // Other models are not gated.
+    assert!(ready("ner").await);
+
+    // Steps replay the response at their index; a step that finds no
+    // response leaves the scenario where it was, and a state without a step
+    // finds none.
+    let (status, _) = admin(
+        &mock,
+        "POST",
+        "/admin/scenarios",
+        json!({
+            "name": "pinned",
+            "initial_state": "second",
+            "steps": [
+                {"state": "second", "rpc": "model_infer", "model": "ner", "index": 1, "next_state": "missing"},
+                {"state": "missing", "rpc": "model_infer", "model": "ner", "index": 5, "next_state": "done"},
+                {"state": "closed", "rpc": "model_ready", "model": "ner", "next_state": "done"}
+            ]
+        }),
+    )
+    .await;
+    assert_eq!(status, 200);
+    let score = |response: server::ModelInferResponse| {
+        tensor::response_output::<f32>(&response, "scores")
+            .unwrap()
+            .1[0]
+    };
+    assert_eq!(score(infer("ner").await.unwrap()), 0.75);
+    assert_eq!(scenario_state(&mock, "pinned").await, "missing");
+    assert!(infer("ner").await.is_err());
+    assert_eq!(scenario_state(&mock, "pinned").await, "missing");
+
+    // model_ready steps without `ready`, and states without a model_ready
+    // step, report the model's own readiness.
+    let (status, _) = admin(
+        &mock,
+        "PUT",
+        "/admin/scenarios/pinned/state",
+        json!({"state": "closed"}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert!(infer("ner").await.is_err());
+    assert!(ready("ner").await);
+    assert_eq!(scenario_state(&mock, "pinned").await, "done");
+    assert!(ready("ner").await);
+    assert!(infer("ner").await.is_err());
+
+    let (status, _) = admin(&mock, "POST", "/admin/scenarios/reset", Value::Null).await;
+    assert_eq!(status, 200);
+    assert_eq!(scenario_state(&mock, "pinned").await, "second");
+    assert_eq!(scenario_state(&mock, "ingestor-retry").await, "Started");
+    mock.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn sessions_replay_independently() {
+    let score = |score: f32| vec![Tensor::new("scores", "FP32", [1], [score]).unwrap()];
+    let mock = MockServer::builder()
+        .recording_bytes(
+            CassetteBuilder::new()
+                .infer("ner", "", Vec::new(), score(0.25))
+                .infer("ner", "", Vec::new(), score(0.75))
+                .to_bytes(),
+        )
+        .start()
+        .await
+        .unwrap();
+    let client = client(&mock).await;
+    let infer = |session: Option<&str>| {
+        let mut client = client.clone();
+        let mut request = tonic::Request::new(ModelInferRequest {
+            model_name: "ner".into(),
+            ..Default::default()
+        });
+        if let Some(session) = session {
+            request
+                .metadata_mut()
+                .insert("x-triton-mock-session", session.parse().unwrap());
+        }
+        async move {
+            let response = client.model_infer(request).await?.into_inner();
+            Ok::<_, tonic::Status>(
+                tensor::response_output::<f32>(&response, "scores")
+                    .unwrap()
+                    .1[0],
+            )
+        }
+    };
+
+    assert_eq!(infer(Some("a")).await.unwrap(), 0.25);
+    assert_eq!(infer(Some("a")).await.unwrap(), 0.75);
+    assert!(infer(Some("a")).await.is_err());
+    assert_eq!(infer(Some("b")).await.unwrap(), 0.25);
+    assert_eq!(infer(None).await.unwrap(), 0.25);
+
+    // Resetting a session rewinds only that session.
+    let (status, _) = admin(
+        &mock,
+        "POST",
+        "/admin/replay/reset",
+        json!({"session": "a"}),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(infer(Some("a")).await.unwrap(), 0.25);
+    assert_eq!(infer(Some("b")).await.unwrap(), 0.75);
+    assert_eq!(infer(None).await.unwrap(), 0.75);
+    mock.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn cassettes_are_chosen_by_header() {
+    let dir = std::env::temp_dir().join(format!("triton-mock-cassettes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let score = |score: f32| vec![Tensor::new("scores", "FP32", [1], [score]).unwrap()];
+    CassetteBuilder::new()
+        .infer("ner", "", Vec::new(), score(0.5))
+        .infer("ner", "", Vec::new(), score(0.6))
+        .save(&dir.join("alpha.json.gz").to_string_lossy())
+        .unwrap();
+    let mock = MockServer::builder()
+        .recording_bytes(recording(0.25))
+        .cassette_dir(&dir)
+        .start()
+        .await
+        .unwrap();
+    let client = client(&mock).await;
+    let infer = |cassette: Option<&str>| {
+        let mut client = client.clone();
+        let mut request = tonic::Request::new(ModelInferRequest {
+            model_name: "ner".into(),
+            ..Default::default()
+        });
+        if let Some(cassette) = cassette {
+            request
+                .metadata_mut()
+                .insert("x-triton-mock-cassette", cassette.parse().unwrap());
+        }
+        async move {
+            let response = client.model_infer(request).await?.into_inner();
+            Ok::<_, tonic::Status>(
+                tensor::response_output::<f32>(&response, "scores")
+                    .unwrap()
+                    .1[0],
+            )
+        }
+    };
+
+    // Requests loading a cassette at the same time share it.
+    let (first, second) = tokio::join!(infer(Some("alpha")), infer(Some("alpha")));
+    let mut scores = [first.unwrap(), second.unwrap()];
+    scores.sort_by(f32::total_cmp);
+    assert_eq!(scores, [0.5, 0.6]);
+    assert_eq!(infer(None).await.unwrap(), 0.25);
+
+    for name in ["../alpha", ".hidden", "a/b"] {
+        let status = infer(Some(name)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument, "{}", name);
+    }
+
+    // A cassette without a file starts out empty, and is only written once
+    // something is recorded into it.
+    let status = infer(Some("fresh")).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    let (_, status) = admin(&mock, "GET", "/admin/status", Value::Null).await;
+    assert_eq!(status["cassettes"], json!(["alpha", "fresh"]));
+    mock.shutdown().await.unwrap();
+    assert!(!dir.join("fresh.json.gz").exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn versions_resolve_like_triton() {
+    use server::model_version_policy::{Latest, PolicyChoice};
+
+    let config = |max_batch_size: i32| ModelConfig {
+        name: "ner".into(),
+        max_batch_size,
+        // Only versions 2 and 3 are loaded.
+        version_policy: Some(server::ModelVersionPolicy {
+            policy_choice: Some(PolicyChoice::Latest(Latest { num_versions: 2 })),
+        }),
+        ..Default::default()
+    };
+    let score = |score: f32| vec![Tensor::new("scores", "FP32", [1], [score]).unwrap()];
+    let mock = MockServer::builder()
+        .recording_bytes(
+            CassetteBuilder::new()
+                .config("", config(0))
+                .config("1", config(1))
+                .config("2", config(2))
+                .config("3", config(3))
+                .infer("ner", "1", Vec::new(), score(1.0))
+                .infer("ner", "2", Vec::new(), score(2.0))
+                .infer("ner", "3", Vec::new(), score(3.0))
+                .infer("ner", "3", Vec::new(), score(3.5))
+                .to_bytes(),
+        )
+        .start()
+        .await
+        .unwrap();
+    let client = client(&mock).await;
+    let infer = |version: &str| {
+        let mut client = client.clone();
+        let request = ModelInferRequest {
+            model_name: "ner".into(),
+            model_version: version.into(),
+            ..Default::default()
+        };
+        async move {
+            let response = client.model_infer(request).await?.into_inner();
+            let score = tensor::response_output::<f32>(&response, "scores")
+                .unwrap()
+                .1[0];
+            Ok::<_, tonic::Status>((response.model_version, score))
+        }
+    };
+    let config = |version: &str| {
+        let mut client = client.clone();
+        let request = ModelConfigRequest {
+            name: "ner".into(),
+            version: version.into(),
+        };
+        async move {
+            let response = client.model_config(request).await?.into_inner();
+            Ok::<_, tonic::Status>(response.config.unwrap().max_batch_size)
+        }
+    };
+    let ready = |version: &str| {
+        let mut client = client.clone();
+        let request = server::ModelReadyRequest {
+            name: "ner".into(),
+            version: version.into(),
+        };
+        async move {
+            client
+                .model_ready(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .ready
+        }
+    };
+
+    assert_eq!(infer("3").await.unwrap(), ("3".to_string(), 3.0));
+    assert_eq!(infer("2").await.unwrap(), ("2".to_string(), 2.0));
+    // An empty version is the latest loaded one.
+    assert_eq!(infer("").await.unwrap(), ("3".to_string(), 3.5));
+    // Versions the policy does not load, or that were never recorded, are
+    // unknown, as on Triton.
+    for version in ["1", "7"] {
+        let status = infer(version).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(
+            status.message(),
+            format!(
+                "Request for unknown model: 'ner' version {} is not found",
+                version
+            )
+        );
+        assert!(!ready(version).await);
+        assert_eq!(
+            config(version).await.unwrap_err().code(),
+            tonic::Code::NotFound
+        );
+    }
+    assert!(ready("").await);
+    assert!(ready("2").await);
+
+    // A config requested without a version is answered from the unversioned
+    // entry first, then from the latest loaded version.
+    assert_eq!(config("").await.unwrap(), 0);
+    assert_eq!(config("").await.unwrap(), 3);
+    assert_eq!(config("2").await.unwrap(), 2);
+
+    let metadata = client
+        .clone()
+        .model_metadata(server::ModelMetadataRequest {
+            name: "ner".into(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(metadata.versions, ["2", "3"]);
+    mock.shutdown().await.unwrap();
+}
+
+/// The little-endian bytes of `values`.
+fn fp32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+/// An HTTP inference request using the binary tensor data extension: the JSON
+/// `header` followed by `binary`, with its length in the header.
+async fn binary_infer(
+    mock: &MockServer,
+    header: Value,
+    binary: &[u8],
+) -> (u16, hyper::HeaderMap, Vec<u8>) {
+    let mut body = serde_json::to_vec(&header).unwrap();
+    let length = body.len().to_string();
+    body.extend_from_slice(binary);
+    http(
+        mock.http_address(),
+        "POST",
+        "/v2/models/ner/infer",
+        &[("inference-header-content-length", length.as_str())],
+        body,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn http_binary_tensor_data() {
+    let outputs = || {
+        vec![
+            Tensor::from_values("scores", [2], &[0.25f32, 0.75]).unwrap(),
+            Tensor::new("labels", "BYTES", [1], ["PER"]).unwrap(),
+        ]
+    };
+    let mock = MockServer::builder()
+        .recording_bytes(
+            CassetteBuilder::new()
+                .infer("ner", "", Vec::new(), outputs())
+                .infer("ner", "", Vec::new(), outputs())
+                // Too few bytes for its shape, so it cannot be converted.
+                .infer(
+                    "ner",
+                    "",
+                    Vec::new(),
+                    vec![Tensor {
+                        name: "scores".into(),
+                        datatype: "FP32".into(),
+                        shape: vec![2],
+                        raw: vec![0; 3],
+                    }],
+                )
+                .to_bytes(),
+        )
+        .start()
+        .await
+        .unwrap();
+
+    // Binary inputs are cut from the body after the JSON header, in input
+    // order; `binary_data_output` returns every output as binary data unless
+    // the output says otherwise.
+    let (status, headers, body) = binary_infer(
+        &mock,
+        json!({
+            "parameters": {"binary_data_output": true},
+            "inputs": [
+                {"name": "first", "datatype": "FP32", "shape": [2], "parameters": {"binary_data_size": 8}},
+                {"name": "inline", "datatype": "INT32", "shape": [1], "data": [7]},
+                {"name": "second", "datatype": "FP32", "shape": [1], "parameters": {"binary_data_size": 4}}
+            ],
+            "outputs": [
+                {"name": "scores"},
+                {"name": "labels", "parameters": {"binary_data": false}}
+            ]
+        }),
+        &[fp32_bytes(&[1.5, 2.5]), fp32_bytes(&[3.5])].concat(),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(headers["content-type"], "application/octet-stream");
+    let length: usize = headers["inference-header-content-length"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let (header, binary) = body.split_at(length);
+    let header: Value = serde_json::from_slice(header).unwrap();
+    assert_eq!(header["outputs"][0]["parameters"]["binary_data_size"], 8);
+    assert!(header["outputs"][0].get("data").is_none());
+    assert_eq!(header["outputs"][1]["data"], json!(["PER"]));
+    assert_eq!(binary, fp32_bytes(&[0.25, 0.75]));
+    let journal = journal(&mock, "rpc=model_infer").await;
+    let inputs = &journal["requests"][0]["request"]["inputs"];
+    assert_eq!(inputs[0]["data"], json!([1.5, 2.5]));
+    assert_eq!(inputs[1]["data"], json!([7]));
+    assert_eq!(inputs[2]["data"], json!([3.5]));
+
+    // Without binary outputs the response is plain JSON.
+    let (status, headers, body) = binary_infer(
+        &mock,
+        json!({"inputs": [{"name": "first", "datatype": "FP32", "shape": [1], "parameters": {"binary_data_size": 4}}]}),
+        &fp32_bytes(&[1.5]),
+    )
+    .await;
+    assert_eq!(status, 200);
+    assert_eq!(headers["content-type"], "application/json");
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["outputs"][0]["data"], json!([0.25, 0.75]));
+
+    // Binary data must match the inputs that claim it.
+    let input = json!({"inputs": [{"name": "first", "datatype": "FP32", "shape": [1], "parameters": {"binary_data_size": 4}}]});
+    for binary in [fp32_bytes(&[1.5, 2.5]), vec![0; 2]] {
+        let (status, _, _) = binary_infer(&mock, input.clone(), &binary).await;
+        assert_eq!(status, 400);
+    }
+    let (status, _, _) = http(
+        mock.http_address(),
+        "POST",
+        "/v2/models/ner/infer",
+        &[("inference-header-content-length", "1000")],
+        serde_json::to_vec(&input).unwrap(),
+    )
+    .await;
+    assert_eq!(status, 400);
+
+    // A recorded response that cannot be converted is the mock's failure.
+    let (status, _, _) = binary_infer(&mock, json!({"inputs": []}), &[]).await;
+    assert_eq!(status, 500);
+    mock.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn traces_are_written_to_the_trace_file() {
+    let dir = std::env::temp_dir().join(format!("triton-mock-trace-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let appended = dir.join("appended.json");
+    let rolled = dir.join("rolled.json");
+    let cassette = (0..5).fold(CassetteBuilder::new(), |cassette, _| {
+        cassette.infer(
+            "ner",
+            "",
+            Vec::new(),
+            vec![Tensor::new("scores", "FP32", [1], [0.5f32]).unwrap()],
+        )
+    });
+    let mock = MockServer::builder()
+        .recording_bytes(cassette.to_bytes())
+        .start()
+        .await
+        .unwrap();
+    let setting = |value: &str| server::trace_setting_request::SettingValue {
+        value: vec![value.to_string()],
+    };
+    let mut client = client(&mock).await;
+    client
+        .trace_setting(server::TraceSettingRequest {
+            settings: HashMap::from([
+                ("trace_level".to_string(), setting("TIMESTAMPS")),
+                ("trace_rate".to_string(), setting("1")),
+                (
+                    "trace_file".to_string(),
+                    setting(appended.to_str().unwrap()),
+                ),
+            ]),
+            model_name: String::new(),
+        })
+        .await
+        .unwrap();
+    infer_score(&mock).await;
+    infer_score(&mock).await;
+
+    // A rollover every two traces leaves the third buffered until shutdown.
+    client
+        .trace_setting(server::TraceSettingRequest {
+            settings: HashMap::from([
+                ("trace_file".to_string(), setting(rolled.to_str().unwrap())),
+                ("log_frequency".to_string(), setting("2")),
+            ]),
+            model_name: String::new(),
+        })
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        infer_score(&mock).await;
+    }
+    mock.shutdown().await.unwrap();
+
+    let read = |path: PathBuf| -> Vec<Value> {
+        serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+    };
+    let ids = |records: &[Value]| -> Vec<u64> {
+        records
+            .iter()
+            .filter(|r| r.get("model_name").is_some())
+            .map(|r| r["id"].as_u64().unwrap())
+            .collect()
+    };
+    assert_eq!(ids(&read(appended)), [1, 2]);
+    assert_eq!(ids(&read(dir.join("rolled.json.0"))), [3, 4]);
+    assert_eq!(ids(&read(dir.join("rolled.json.1"))), [5]);
+    std::fs::remove_dir_all(dir).unwrap();
+}